@@ -0,0 +1,346 @@
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, Metadata},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::path::{cache_home, get_path_summary_par, HardLinks, SizeMode, Summary};
+use crate::progress;
+
+/// Sizes of the directories seen in previous runs, stored as a JSON object
+/// keyed by path. A directory is not read again as long as its modification
+/// and status change times are unchanged, which holds as long as no entry is
+/// added, removed or renamed. Files rewritten in place are only noticed once
+/// their directory changes, use `--no-cache` to measure everything again.
+#[derive(Debug)]
+pub(crate) struct SizeCache {
+    file_path: PathBuf,
+    /// Entries loaded from the file.
+    cached: HashMap<PathBuf, CachedDir>,
+    /// Entries of the directories measured during this run.
+    visited: Mutex<HashMap<PathBuf, CachedDir>>,
+    /// Paths removed during this run, whose entries are dropped.
+    forgotten: Mutex<Vec<PathBuf>>,
+}
+
+/// Directories changed shortly before they were read may change again
+/// without a visible difference in their timestamps.
+const RACY_WINDOW: Duration = Duration::from_secs(1);
+
+/// The direct entries of a directory, its subdirectories are cached on their own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedDir {
+    ino: u64,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+    /// Milliseconds since the epoch when the entries were read.
+    read_at: u64,
+    /// Apparent size of the files which are not hard linked.
+    apparent: u64,
+    /// Allocated size of the directory and of the files which are not hard linked.
+    disk: u64,
+    /// Number of the entries which are not directories.
+    files: u64,
+    /// Most recent modification of the directory and of its files.
+    newest: i64,
+    /// Names of the subdirectories.
+    dirs: Vec<String>,
+    links: Vec<CachedLink>,
+}
+
+/// A file with more than one link, which is only counted once per run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedLink {
+    name: String,
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    apparent: u64,
+    disk: u64,
+}
+
+impl CachedDir {
+    /// Reads the direct entries of the directory. Returns `None` if a
+    /// name is not valid UTF-8 and thus cannot be stored.
+    fn read(path: &Path, meta: &Metadata) -> io::Result<Option<Self>> {
+        let mut dir = Self {
+            ino: meta.ino(),
+            mtime: (meta.mtime(), meta.mtime_nsec()),
+            ctime: (meta.ctime(), meta.ctime_nsec()),
+            read_at: millis_since_epoch(SystemTime::now()),
+            apparent: 0,
+            disk: meta.blocks() * 512,
+            files: 0,
+            newest: meta.mtime(),
+            dirs: Vec::new(),
+            links: Vec::new(),
+        };
+
+        for entry in fs::read_dir(path)?.filter_map(|entry| entry.ok()) {
+            let Ok(name) = entry.file_name().into_string() else {
+                return Ok(None);
+            };
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+
+            if meta.is_dir() {
+                dir.dirs.push(name);
+                continue;
+            }
+            dir.files += 1;
+            dir.newest = dir.newest.max(meta.mtime());
+            if !meta.is_file() && !meta.is_symlink() {
+                continue;
+            } else if meta.nlink() > 1 {
+                dir.links.push(CachedLink {
+                    name,
+                    dev: meta.dev(),
+                    ino: meta.ino(),
+                    nlink: meta.nlink(),
+                    apparent: meta.len(),
+                    disk: meta.blocks() * 512,
+                });
+            } else {
+                dir.apparent += meta.len();
+                dir.disk += meta.blocks() * 512;
+            }
+        }
+
+        Ok(Some(dir))
+    }
+
+    /// Returns `true` if the directory was not changed since it was cached
+    /// and was not changed right before it was cached either.
+    fn is_fresh(&self, meta: &Metadata) -> bool {
+        let changed_at = (self.ctime.0 as u64) * 1000 + (self.ctime.1 as u64) / 1_000_000;
+        changed_at + (RACY_WINDOW.as_millis() as u64) < self.read_at
+            && self.ino == meta.ino()
+            && self.mtime == (meta.mtime(), meta.mtime_nsec())
+            && self.ctime == (meta.ctime(), meta.ctime_nsec())
+    }
+}
+
+impl SizeCache {
+    /// Opens the cache in the cache directory of the current user.
+    pub(crate) fn home() -> Result<Self> {
+        Ok(Self::new(Self::home_path()?))
+    }
+
+    fn home_path() -> Result<PathBuf> {
+        let cache_home = cache_home().ok_or_else(|| anyhow!("cannot find cache directory"))?;
+        Ok(cache_home.join("clir").join("sizes"))
+    }
+
+    /// Loads the cache from the file, a missing or invalid file is treated as empty.
+    pub(crate) fn new(file_path: PathBuf) -> Self {
+        let cached = match fs::read(&file_path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                log::warn!("ignoring invalid size cache {file_path:?}: {err}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            file_path,
+            cached,
+            visited: Mutex::default(),
+            forgotten: Mutex::default(),
+        }
+    }
+
+    /// Removes the cache of the current user.
+    pub(crate) fn clear() -> Result<()> {
+        let file_path = Self::home_path()?;
+        match fs::remove_file(&file_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("failed to remove {file_path:?}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the summary of the directory and its subtree like
+    /// `get_path_summary_par`, or `None` if the directory cannot be cached.
+    pub(crate) fn dir_summary(
+        &self,
+        path: &Path,
+        meta: &Metadata,
+        size_mode: SizeMode,
+        hard_links: Option<&HardLinks>,
+        device: Option<u64>,
+    ) -> Option<Summary> {
+        path.to_str()?;
+        let dir = match self.cached.get(path).filter(|dir| dir.is_fresh(meta)) {
+            Some(dir) => dir.clone(),
+            None => CachedDir::read(path, meta).ok()??,
+        };
+
+        let mut size = match size_mode {
+            SizeMode::Apparent => dir.apparent,
+            SizeMode::Disk => dir.disk,
+        };
+        for link in &dir.links {
+            let link_size = match size_mode {
+                SizeMode::Apparent => link.apparent,
+                SizeMode::Disk => link.disk,
+            };
+            match hard_links {
+                Some(hard_links) => hard_links.insert(
+                    path.join(&link.name),
+                    (link.dev, link.ino),
+                    link.nlink,
+                    link_size,
+                ),
+                None => size += link_size,
+            }
+        }
+        progress::add_scanned(1 + dir.links.len() as u64, size);
+        let summary = Summary {
+            size,
+            count: 1 + dir.files,
+            newest: Some(dir.newest),
+        };
+        let summary = dir
+            .dirs
+            .par_iter()
+            .map(|name| {
+                get_path_summary_par(
+                    path.join(name),
+                    None,
+                    size_mode,
+                    hard_links,
+                    device,
+                    Some(self),
+                )
+            })
+            .reduce(Summary::default, Summary::merge)
+            .merge(summary);
+
+        let mut visited = self.visited.lock().unwrap_or_else(|e| e.into_inner());
+        visited.insert(path.to_path_buf(), dir);
+        Some(summary)
+    }
+
+    /// Drops the entries of the path and its subtree when saving.
+    pub(crate) fn forget(&self, path: &Path) {
+        let mut forgotten = self.forgotten.lock().unwrap_or_else(|e| e.into_inner());
+        forgotten.push(path.to_path_buf());
+    }
+
+    /// Writes the directories measured during this run together with the
+    /// cached ones which were not reached, as long as they still exist.
+    /// Cached subdirectories of measured directories which were not reached
+    /// no longer exist and are dropped without looking them up.
+    pub(crate) fn save(&self) -> Result<()> {
+        let visited = self.visited.lock().unwrap_or_else(|e| e.into_inner());
+        let forgotten = self.forgotten.lock().unwrap_or_else(|e| e.into_inner());
+        if visited.is_empty() && forgotten.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries: HashMap<&PathBuf, &CachedDir> = self
+            .cached
+            .par_iter()
+            .filter(|(path, _)| !path.parent().is_some_and(|p| visited.contains_key(p)))
+            .filter(|(path, _)| !visited.contains_key(*path))
+            // e.g. the directories of a rule removed since, or of a project
+            // deleted outside of clir
+            .filter(|(path, _)| fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()))
+            .collect();
+        entries.extend(visited.iter());
+        entries.retain(|path, _| !forgotten.iter().any(|f| path.starts_with(f)));
+
+        let dir = self.file_path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+        // concurrent runs must never see a partially written cache
+        let tmp_path = self.file_path.with_extension(process::id().to_string());
+        fs::write(&tmp_path, serde_json::to_vec(&entries)?)
+            .with_context(|| format!("failed to write {tmp_path:?}"))?;
+        fs::rename(&tmp_path, &self.file_path)
+            .with_context(|| format!("failed to write {:?}", self.file_path))
+    }
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SizeCache;
+    use crate::path::{get_path_size_par, get_path_summary_par, SizeMode};
+    use std::{fs, path::Path, thread, time::Duration};
+
+    fn size(path: &Path, cache: &SizeCache) -> u64 {
+        get_path_size_par(path, None, SizeMode::Apparent, None, None, Some(cache))
+    }
+
+    #[test]
+    fn unchanged_directories_are_not_read() {
+        let root = std::env::temp_dir().join(format!("clir-cache-{}", std::process::id()));
+        let file_path = root.join("sizes");
+        let dir = root.join("target");
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("a/b/c.tmp"), [0; 8]).unwrap();
+        fs::write(dir.join("d.tmp"), [0; 4]).unwrap();
+        // directories changed right before they are read are not reused
+        thread::sleep(Duration::from_millis(1100));
+
+        let cache = SizeCache::new(file_path.clone());
+        assert_eq!(size(&dir, &cache), 12);
+        cache.save().unwrap();
+
+        // cached directories are counted like read ones
+        let cache = SizeCache::new(file_path.clone());
+        let summary =
+            |cache| get_path_summary_par(&dir, None, SizeMode::Apparent, None, None, cache);
+        assert_eq!(summary(Some(&cache)), summary(None));
+        assert_eq!(summary(None).count, 5);
+
+        // growing a file in place does not change its directory
+        fs::write(dir.join("a/b/c.tmp"), [0; 16]).unwrap();
+        let cache = SizeCache::new(file_path.clone());
+        assert_eq!(size(&dir, &cache), 12);
+
+        // adding an entry does
+        fs::write(dir.join("a/b/e.tmp"), [0; 2]).unwrap();
+        assert_eq!(size(&dir, &cache), 22);
+        cache.save().unwrap();
+
+        // removed directories are dropped from the cache
+        fs::remove_dir_all(dir.join("a/b")).unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        let cache = SizeCache::new(file_path.clone());
+        assert_eq!(size(&dir, &cache), 4);
+        cache.save().unwrap();
+        let cache = SizeCache::new(file_path.clone());
+        assert!(!cache.cached.contains_key(&dir.join("a/b")));
+        assert!(cache.cached.contains_key(&dir.join("a")));
+
+        // directories which were not reached are kept while they exist
+        let other = root.join("other");
+        fs::create_dir_all(&other).unwrap();
+        size(&other, &cache);
+        cache.save().unwrap();
+        fs::remove_dir_all(dir.join("a")).unwrap();
+        let cache = SizeCache::new(file_path.clone());
+        size(&other, &cache);
+        cache.save().unwrap();
+        let cache = SizeCache::new(file_path);
+        assert!(cache.cached.contains_key(&dir));
+        assert!(!cache.cached.contains_key(&dir.join("a")));
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
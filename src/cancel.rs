@@ -0,0 +1,80 @@
+use std::{
+    io, mem, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::Status;
+
+/// Set by the signal handler, workers stop after their current entry once it is set.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+extern "C" fn handle_signal(_: libc::c_int) {
+    // a second signal exits right away, without waiting for the workers
+    if CANCELLED.swap(true, Ordering::SeqCst) {
+        // SAFETY: `_exit` is async-signal-safe.
+        unsafe { libc::_exit(Status::Cancelled.code()) };
+    }
+}
+
+/// Turns SIGINT and SIGTERM into a cancellation request until it is
+/// dropped, which restores the previous handlers.
+pub(crate) struct Handler {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl Handler {
+    pub(crate) fn install() -> io::Result<Self> {
+        CANCELLED.store(false, Ordering::SeqCst);
+
+        let mut handler = Self {
+            previous: Vec::with_capacity(SIGNALS.len()),
+        };
+        for signal in SIGNALS {
+            // SAFETY: both structs are plain old data and fully initialized
+            // below or by `sigaction`, the handler only touches an atomic.
+            unsafe {
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as usize;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous: libc::sigaction = mem::zeroed();
+                if libc::sigaction(signal, &action, &mut previous) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                handler.previous.push((signal, previous));
+            }
+        }
+
+        Ok(handler)
+    }
+
+    /// Returns the flag which is set once a signal was received.
+    pub(crate) fn cancelled(&self) -> &'static AtomicBool {
+        &CANCELLED
+    }
+}
+
+impl Drop for Handler {
+    fn drop(&mut self) {
+        for (signal, previous) in &self.previous {
+            // SAFETY: `previous` was returned by `sigaction` for this signal.
+            unsafe { libc::sigaction(*signal, previous, ptr::null_mut()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Handler;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn signal_cancels() {
+        let handler = Handler::install().unwrap();
+        assert!(!handler.cancelled().load(Ordering::SeqCst));
+        // SAFETY: the handler installed above only sets a flag.
+        unsafe { libc::raise(libc::SIGTERM) };
+        assert!(handler.cancelled().load(Ordering::SeqCst));
+    }
+}
@@ -1,89 +1,329 @@
-use std::io::{stdin, stdout, Write};
+use std::cell::OnceCell;
+use std::io::{stderr, stdin, stdout, Write};
 use std::path::Path;
 use std::string::String;
+use std::sync::Arc;
 use std::time;
 
-use anyhow::{Ok, Result};
+use anyhow::{anyhow, Ok, Result};
 
-use crate::display;
-use crate::path::PathTree;
+use crate::cache::SizeCache;
+use crate::cancel;
+use crate::config::NEGATION;
+use crate::display::{self, OutputFormat};
+use crate::journal::Journal;
+use crate::path::{canonicalize, PathTree, SizeMode};
+use crate::preset;
+use crate::progress;
+use crate::report::{self, ReportFormat};
 use crate::rules::{Pattern, Rules};
+use crate::select;
+use crate::trash;
+use crate::tui;
+use crate::Status;
 
 pub(crate) struct Command<'a> {
     rules: Rules<'a>,
     workdir: &'a Path,
     absolute_path: bool,
+    trash: Option<bool>,
+    format: OutputFormat,
+    size_mode: SizeMode,
+    use_cache: bool,
+    /// Loaded on first use, since most commands do not measure sizes.
+    cache: OnceCell<Option<Arc<SizeCache>>>,
 }
 
 impl<'a> Command<'a> {
-    pub(crate) fn new(rules: Rules<'a>, workdir: &'a Path, absolute_path: bool) -> Command<'a> {
+    pub(crate) fn new(
+        rules: Rules<'a>,
+        workdir: &'a Path,
+        absolute_path: bool,
+        trash: Option<bool>,
+        format: OutputFormat,
+        size_mode: SizeMode,
+        use_cache: bool,
+    ) -> Command<'a> {
         Command {
             rules,
             workdir,
             absolute_path,
+            trash,
+            format,
+            size_mode,
+            use_cache,
+            cache: OnceCell::new(),
         }
     }
 
-    pub(crate) fn add_rules(&mut self, rules: Vec<&String>) -> Result<()> {
-        self.rules.add(self.prefix_workdir(rules)?)
+    pub(crate) fn add_rules(
+        &mut self,
+        rules: Vec<&String>,
+        name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<()> {
+        self.rules
+            .add(self.prefix_workdir(rules)?, name, description)
     }
 
     pub(crate) fn remove_rules(&mut self, rules: Vec<&String>) -> Result<()> {
         self.rules.remove(self.prefix_workdir(rules)?)
     }
 
-    pub(crate) fn list(&self) -> Result<Vec<Pattern>> {
-        let mut path_tree = PathTree::new();
-        let patterns = self.rules.expand_patterns(&mut path_tree);
-        display::format_patterns(self.workdir, &path_tree, &patterns, self.absolute_path)?;
+    pub(crate) fn remove_named_rules(&mut self, names: Vec<&String>) -> Result<()> {
+        self.rules.remove_named(names)
+    }
+
+    pub(crate) fn set_rules_enabled(&mut self, names: Vec<&String>, enabled: bool) -> Result<()> {
+        self.rules.set_enabled(names, enabled)
+    }
+
+    pub(crate) fn list(&self) -> Result<Vec<Pattern<'_>>> {
+        let patterns = self.expand_patterns();
+        self.save_cache();
+        self.print_patterns(&patterns)?;
         Ok(patterns)
     }
 
-    pub(crate) fn clean_with_confirmation(&self) -> Result<()> {
+    fn expand_patterns(&self) -> Vec<Pattern<'_>> {
+        let progress = progress::Reporter::start();
+        let mut path_tree = self.path_tree();
+        let patterns = self.rules.expand_patterns(&mut path_tree);
+        drop(progress);
+        patterns
+    }
+
+    fn print_patterns(&self, patterns: &[Pattern]) -> Result<()> {
+        match self.format {
+            OutputFormat::Table => {
+                display::format_patterns(self.workdir, patterns, self.absolute_path, self.size_mode)
+            }
+            OutputFormat::Json => display::format_patterns_json(patterns, self.size_mode),
+        }
+    }
+
+    pub(crate) fn clean_with_confirmation(&self) -> Result<Status> {
         let patterns = self.list()?;
         if patterns.is_empty() {
-            return Ok(());
+            return Ok(Status::Success);
         }
 
-        print!("\nClean all selected paths? [(Y)es/(N)o]: ");
-        stdout().lock().flush()?;
+        // stdout only contains the report in JSON mode
+        let mut output: Box<dyn Write> = match self.format {
+            OutputFormat::Json => Box::new(stderr()),
+            OutputFormat::Table => Box::new(stdout()),
+        };
+        write!(
+            output,
+            "\nClean all selected paths? [(Y)es/(N)o/(S)elect]: "
+        )?;
+        output.flush()?;
 
         let mut confirm = String::new();
         stdin().read_line(&mut confirm)?;
         let confirm = confirm.to_ascii_lowercase();
         let confirm = confirm.trim();
 
-        if confirm == "y" || confirm == "yes" {
-            self.clean(&patterns)?;
-        } else {
-            println!("Aborting...");
-        }
+        let patterns = match confirm {
+            "y" | "yes" => Some(patterns),
+            "s" | "select" => select::select(
+                patterns,
+                self.workdir,
+                self.absolute_path,
+                &mut stdin().lock(),
+                &mut output,
+            )?
+            .filter(|patterns| !patterns.is_empty()),
+            _ => None,
+        };
 
-        Ok(())
+        match patterns {
+            Some(patterns) => self.clean(&patterns),
+            None => {
+                writeln!(output, "Aborting...")?;
+                Ok(Status::Aborted)
+            }
+        }
     }
 
-    fn clean(&self, patterns: &Vec<Pattern>) -> Result<()> {
+    fn clean(&self, patterns: &Vec<Pattern>) -> Result<Status> {
         let start = time::Instant::now();
-        self.rules.clean(patterns)?;
+        // keep handling signals until the journal is written
+        let handler = cancel::Handler::install()?;
+        let progress = progress::Reporter::start();
+        let report = self.rules.clean(patterns, self.trash, handler.cancelled());
+        drop(progress);
+        let report = report?;
         let elapsed = start.elapsed().as_millis();
-        println!("Finished in {:.2}s", (elapsed as f64) / 1000.);
+        if self.format == OutputFormat::Table {
+            println!("Finished in {:.2}s", (elapsed as f64) / 1000.);
+        }
+        display::format_clean_report(&report, self.format)?;
+        if let Some(cache) = self.cache.get().and_then(Option::as_ref) {
+            report.entries.iter().for_each(|e| cache.forget(&e.path));
+            report.partial.iter().for_each(|path| cache.forget(path));
+            self.save_cache();
+        }
+
+        let status = match (report.is_cancelled(), report.failures.is_empty()) {
+            (true, _) => Status::Cancelled,
+            (_, true) => Status::Success,
+            (_, false) => Status::PartialFailure,
+        };
+        if !report.entries.is_empty() {
+            // the paths are gone already, failing now would hide the status of the run
+            if let Err(err) = Journal::home()
+                .and_then(|journal| journal.append(report.entries))
+                .map(|id| log::info!("recorded clean run #{id} in journal"))
+            {
+                eprintln!("warning: failed to record the clean run in the journal: {err:#}");
+            }
+        }
+        Ok(status)
+    }
+
+    pub(crate) fn show_presets(&self, names: Vec<&String>) -> Result<()> {
+        let presets = match names.is_empty() {
+            true => preset::PRESETS.iter().collect(),
+            false => names
+                .into_iter()
+                .map(|name| preset::get(name).ok_or_else(|| anyhow!("unknown preset `{name}`")))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        display::format_presets(&presets)
+    }
+
+    /// Adds the rules of the presets under the root,
+    /// which defaults to the working directory.
+    pub(crate) fn add_presets(&mut self, names: Vec<&String>, root: Option<&String>) -> Result<()> {
+        let root = self.workdir.join(root.map_or("", String::as_str));
+        let root = canonicalize(&root).ok_or_else(|| anyhow!("invalid root {root:?}"))?;
+        for name in names {
+            let preset = preset::get(name).ok_or_else(|| anyhow!("unknown preset `{name}`"))?;
+            self.rules.add_preset(preset, &root)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn history(&self) -> Result<()> {
+        let runs = Journal::home()?.runs()?;
+        display::format_history(&runs)
+    }
+
+    /// Puts back all recoverable paths of the given run,
+    /// or of the last run if no id is given.
+    pub(crate) fn restore(&self, id: Option<u64>) -> Result<()> {
+        let run = Journal::home()?.get(id)?;
+        let mut num_restored = 0;
+        for entry in &run.entries {
+            let Some(location) = entry.location.as_ref().filter(|_| entry.is_recoverable()) else {
+                println!("cannot restore {}: not recoverable", entry.path.display());
+                continue;
+            };
+            if let Err(err) = trash::restore(location, &entry.path) {
+                println!("cannot restore {}: {err}", entry.path.display());
+                continue;
+            }
+            log::info!("restored {:?} from {location:?}", entry.path);
+            num_restored += 1;
+        }
+
+        println!(
+            "Restored {num_restored} of {} paths from run #{}",
+            run.entries.len(),
+            run.id
+        );
         Ok(())
     }
 
-    pub(crate) fn clean_all(&self) -> Result<()> {
+    pub(crate) fn clean_all(&self) -> Result<Status> {
         let patterns = self.list()?;
         if patterns.is_empty() {
-            return Ok(());
+            return Ok(Status::Success);
         }
 
         self.clean(&patterns)
     }
 
+    /// Opens the explorer and cleans the paths marked in it.
+    pub(crate) fn tui(&self) -> Result<Status> {
+        let progress = progress::Reporter::start();
+        let mut path_tree = self.path_tree();
+        let patterns = self.rules.expand_patterns(&mut path_tree);
+        drop(progress);
+        let selection = tui::explore(&patterns, &path_tree);
+        self.save_cache();
+        match selection? {
+            Some(patterns) => self.clean(&patterns),
+            None => Ok(Status::Success),
+        }
+    }
+
+    /// Prints one record per matched path, including
+    /// the paths shadowed by overlapping patterns.
+    pub(crate) fn report(&self, format: ReportFormat) -> Result<()> {
+        let progress = progress::Reporter::start();
+        let mut path_tree = self.path_tree();
+        let patterns = self.rules.expand_all_patterns(&mut path_tree);
+        drop(progress);
+        self.save_cache();
+        report::format_report(&patterns, format, self.size_mode)
+    }
+
+    /// Lists the matched paths a clean run would remove, but not the
+    /// files below them. Unlike `list`, nothing is written to disk.
+    pub(crate) fn dry_run(&self) -> Result<()> {
+        let patterns = self.expand_patterns();
+        self.print_patterns(&patterns)?;
+        // fail on the same paths as the clean run
+        self.rules.check_protected(&patterns)?;
+        // the json report already contains every path
+        if patterns.is_empty() || self.format == OutputFormat::Json {
+            return Ok(());
+        }
+
+        display::format_dry_run(self.workdir, &patterns, self.absolute_path)
+    }
+
+    pub(crate) fn clear_cache(&self) -> Result<()> {
+        SizeCache::clear()?;
+        println!("Cleared the size cache");
+        Ok(())
+    }
+
+    fn path_tree(&self) -> PathTree {
+        let cache = self.cache.get_or_init(|| {
+            if !self.use_cache {
+                return None;
+            }
+            SizeCache::home()
+                .map_err(|err| log::warn!("not using the size cache: {err}"))
+                .ok()
+                .map(Arc::new)
+        });
+        PathTree::with_size_mode(self.size_mode).with_cache(cache.clone())
+    }
+
+    /// Persists the measured sizes, a failure only costs speed on the next run.
+    fn save_cache(&self) {
+        let Some(cache) = self.cache.get().and_then(Option::as_ref) else {
+            return;
+        };
+        if let Err(err) = cache.save() {
+            log::warn!("failed to save the size cache: {err:#}");
+        }
+    }
+
     fn prefix_workdir(&self, rules: Vec<&String>) -> Result<Vec<String>> {
         let mut paths: Vec<String> = Vec::new();
         for r in rules {
+            // keep the negation prefix in front of the path
+            let (prefix, r) = match r.strip_prefix(NEGATION) {
+                Some(r) => (NEGATION.to_string(), r),
+                None => (String::new(), r.as_str()),
+            };
             if let Some(path) = self.workdir.join(r).to_str() {
-                paths.push(path.to_owned())
+                paths.push(prefix + path)
             }
         }
         Ok(paths)
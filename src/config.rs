@@ -0,0 +1,478 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Content of the TOML rules file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    #[serde(default)]
+    pub(crate) options: Options,
+    #[serde(default)]
+    pub(crate) rules: BTreeMap<String, Rule>,
+}
+
+/// Global options which apply to all rules.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Options {
+    /// Move paths to the trash instead of deleting them.
+    #[serde(default)]
+    pub(crate) trash: bool,
+    /// Never descend into other file systems, e.g. bind mounts,
+    /// while calculating sizes and cleaning.
+    #[serde(default)]
+    pub(crate) one_file_system: bool,
+    /// Paths which are never cleaned in addition to the built-in ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) protected: Vec<PathBuf>,
+    /// Number of threads used to scan and clean, defaults to one per core.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jobs: Option<NonZeroUsize>,
+    /// Run with the lowest CPU priority and the idle I/O class.
+    #[serde(default)]
+    pub(crate) low_priority: bool,
+}
+
+/// A named set of glob patterns.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Rule {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) description: String,
+    #[serde(default = "enabled_default")]
+    pub(crate) enabled: bool,
+    pub(crate) globs: Vec<PathBuf>,
+    #[serde(flatten)]
+    pub(crate) options: RuleOptions,
+    /// Keys left over by `options`, `deny_unknown_fields` does not
+    /// work through `flatten`, so they are rejected after parsing.
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// Options which only apply to the patterns of a single rule.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct RuleOptions {
+    /// Overrides the global `trash` option for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) trash: Option<bool>,
+    /// Globs of paths which are never cleaned by this rule.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) exclude: Vec<PathBuf>,
+    /// Only matches with a sibling of this name, e.g. `Cargo.toml`
+    /// next to `target`, are cleaned. May contain wildcards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) marker: Option<String>,
+    /// Only matches whose newest entry is older than this are cleaned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) older_than: Option<Age>,
+    /// Only matches of at least this size are cleaned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) min_size: Option<Size>,
+    /// Only matches of at most this size are cleaned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_size: Option<Size>,
+    /// The timestamp `older_than` is compared with.
+    #[serde(default, skip_serializing_if = "TimeKind::is_default")]
+    pub(crate) age_by: TimeKind,
+    /// Overrides the global `one_file_system` option for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) one_file_system: Option<bool>,
+    /// How matches which are symlinks are handled.
+    #[serde(default, skip_serializing_if = "Symlinks::is_default")]
+    pub(crate) symlinks: Symlinks,
+}
+
+/// Duration given in the form `<n><unit>`, e.g. `14d`
+/// with one of the units `s`, `m`, `h`, `d` or `w`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Age(pub(crate) Duration);
+
+impl FromStr for Age {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let n: u64 = n.parse().with_context(|| format!("invalid age `{s}`"))?;
+        let secs = match unit.trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => anyhow::bail!("invalid unit of age `{s}`, expected one of s, m, h, d, w"),
+        };
+        n.checked_mul(secs)
+            .map(|secs| Self(Duration::from_secs(secs)))
+            .ok_or_else(|| anyhow::anyhow!("age `{s}` is too large"))
+    }
+}
+
+impl TryFrom<String> for Age {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Age> for String {
+    fn from(age: Age) -> Self {
+        let secs = age.0.as_secs();
+        let (n, unit) = [
+            (7 * 24 * 60 * 60, "w"),
+            (24 * 60 * 60, "d"),
+            (60 * 60, "h"),
+            (60, "m"),
+        ]
+        .into_iter()
+        .find(|(unit_secs, _)| secs > 0 && secs.is_multiple_of(*unit_secs))
+        .map_or((secs, "s"), |(unit_secs, unit)| (secs / unit_secs, unit));
+        format!("{n}{unit}")
+    }
+}
+
+/// Size in bytes given in the form `<n><unit>`, e.g. `100MiB`, with
+/// decimal (`KB`, `MB`, ...) or binary (`KiB`, `MiB`, ...) units.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Size(pub(crate) u64);
+
+const SIZE_UNITS: [(&str, u64); 9] = [
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+    ("TB", 1_000_000_000_000),
+    ("GB", 1_000_000_000),
+    ("MB", 1_000_000),
+    ("KB", 1_000),
+    ("B", 1),
+];
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let n: u64 = n.parse().with_context(|| format!("invalid size `{s}`"))?;
+        let unit = match unit.trim() {
+            "" => 1,
+            unit => SIZE_UNITS
+                .iter()
+                .find(|(u, _)| u.eq_ignore_ascii_case(unit))
+                .map(|(_, bytes)| *bytes)
+                .ok_or_else(|| anyhow::anyhow!("invalid unit of size `{s}`"))?,
+        };
+        n.checked_mul(unit)
+            .map(Self)
+            .ok_or_else(|| anyhow::anyhow!("size `{s}` is too large"))
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Size> for String {
+    fn from(size: Size) -> Self {
+        let (unit, bytes) = SIZE_UNITS
+            .iter()
+            .find(|(_, bytes)| size.0 > 0 && size.0.is_multiple_of(*bytes))
+            .unwrap_or(&("B", 1));
+        format!("{}{unit}", size.0 / bytes)
+    }
+}
+
+/// Timestamp of a file used to determine its age.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TimeKind {
+    /// Time of the last modification.
+    #[default]
+    Mtime,
+    /// Time of the last access.
+    Atime,
+    /// Time of the last status change.
+    Ctime,
+}
+
+impl TimeKind {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Handling of matches which are symlinks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Symlinks {
+    /// Ignore the symlink.
+    Skip,
+    /// Clean the symlink itself and leave its target untouched.
+    #[default]
+    RemoveLink,
+    /// Clean the target of the symlink instead.
+    Follow,
+}
+
+impl Symlinks {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Prefix of globs which exclude paths from all rules.
+pub(crate) const NEGATION: char = '!';
+
+/// Strips the negation prefix of a glob and returns `None` if the glob is not negated.
+pub(crate) fn strip_negation(glob: &Path) -> Option<&Path> {
+    glob.to_str()?.strip_prefix(NEGATION).map(Path::new)
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+impl Rule {
+    pub(crate) fn new(description: String, globs: Vec<PathBuf>) -> Self {
+        Self {
+            description,
+            enabled: true,
+            globs,
+            options: RuleOptions::default(),
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content).context("failed to parse rules file")?;
+        // a misspelled filter would otherwise match and clean everything
+        for (name, rule) in &config.rules {
+            if let Some(key) = rule.unknown.keys().next() {
+                bail!("failed to parse rules file: unknown key `{key}` in rule `{name}`");
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parses the line-based rules file of older versions, which
+    /// contains one glob pattern or `key = value` option per line.
+    /// Fails on any other line, since the content is most likely
+    /// a malformed TOML file instead.
+    pub(crate) fn parse_legacy(content: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for line in content.split('\n') {
+            // ignore emtpy lines
+            if line.is_empty() || config.options.parse_line(line)? {
+                continue;
+            }
+
+            let glob = PathBuf::from(line);
+            if !strip_negation(&glob).unwrap_or(&glob).is_absolute() {
+                bail!("invalid legacy rule `{line}`");
+            }
+            let name = config.rule_name(&glob);
+            config
+                .rules
+                .insert(name, Rule::new(String::new(), vec![glob]));
+        }
+
+        Ok(config)
+    }
+
+    pub(crate) fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self).context("failed to serialize rules")
+    }
+
+    /// Returns `true` if any rule already contains the glob.
+    pub(crate) fn contains_glob(&self, glob: &Path) -> bool {
+        self.rules
+            .values()
+            .any(|r| r.globs.iter().any(|g| g == glob))
+    }
+
+    /// Derives an unused rule name from the last
+    /// component of the glob which is not a wildcard.
+    pub(crate) fn rule_name(&self, glob: &Path) -> String {
+        let base = glob
+            .iter()
+            .rev()
+            .filter_map(|c| c.to_str())
+            .find(|c| *c != "/" && !c.contains(['*', '?', '[']))
+            .unwrap_or("rule");
+
+        self.unique_name(base)
+    }
+
+    /// Appends a counter to the name if a rule of that name exists.
+    pub(crate) fn unique_name(&self, base: &str) -> String {
+        let mut name = base.to_owned();
+        for i in 2.. {
+            if !self.rules.contains_key(&name) {
+                break;
+            }
+            name = format!("{base}-{i}");
+        }
+        name
+    }
+}
+
+impl Options {
+    /// Parses a single legacy option line and returns `false`
+    /// if the line is not an option.
+    fn parse_line(&mut self, line: &str) -> Result<bool> {
+        let Some((key, value)) = line.split_once('=') else {
+            return Ok(false);
+        };
+
+        match key.trim() {
+            "trash" => {
+                self.trash = value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("invalid value for option `trash`: {value}"))?
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_negation, Age, Config, Size, TimeKind};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_legacy() {
+        let config =
+            Config::parse_legacy("trash = true\n/tmp/a/target\n/tmp/b/target\n\n/tmp/**/*.rs\n")
+                .unwrap();
+
+        assert!(config.options.trash);
+        assert_eq!(
+            config.rules.keys().collect::<Vec<_>>(),
+            vec!["target", "target-2", "tmp"]
+        );
+        assert_eq!(
+            config.rules["tmp"].globs,
+            vec![PathBuf::from("/tmp/**/*.rs")]
+        );
+        assert!(Config::parse_legacy("!/tmp/keep\n/tmp/*.log\n").is_ok());
+        assert!(Config::parse_legacy("[options]\ntrsh = true\n").is_err());
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let content = r#"
+[options]
+trash = true
+
+[rules.rust]
+description = "build artifacts"
+enabled = false
+globs = ["/tmp/**/target"]
+trash = false
+"#;
+        let config = Config::parse(content).unwrap();
+        let rule = &config.rules["rust"];
+        assert_eq!(rule.description, "build artifacts");
+        assert!(!rule.enabled);
+        assert_eq!(rule.options.trash, Some(false));
+
+        let config = Config::parse(&config.to_toml().unwrap()).unwrap();
+        assert!(config.options.trash);
+        assert!(config.contains_glob(Path::new("/tmp/**/target")));
+    }
+
+    #[test]
+    fn negated_globs() {
+        assert_eq!(
+            strip_negation(Path::new("!/tmp/keep")),
+            Some(Path::new("/tmp/keep"))
+        );
+        assert_eq!(strip_negation(Path::new("/tmp/!keep")), None);
+    }
+
+    #[test]
+    fn parse_age() {
+        assert_eq!(
+            "14d".parse::<Age>().unwrap().0,
+            Duration::from_secs(14 * 86400)
+        );
+        assert_eq!("90s".parse::<Age>().unwrap().0, Duration::from_secs(90));
+        assert!("14".parse::<Age>().is_err());
+        assert!("d".parse::<Age>().is_err());
+        assert!("3y".parse::<Age>().is_err());
+        assert!("100000000000000w".parse::<Age>().is_err());
+
+        assert_eq!(String::from(Age(Duration::from_secs(14 * 86400))), "2w");
+        assert_eq!(String::from(Age(Duration::from_secs(90))), "90s");
+    }
+
+    #[test]
+    fn parse_size() {
+        assert_eq!("100MiB".parse::<Size>().unwrap().0, 100 << 20);
+        assert_eq!("2kb".parse::<Size>().unwrap().0, 2000);
+        assert_eq!("512".parse::<Size>().unwrap().0, 512);
+        assert!("1PB".parse::<Size>().is_err());
+        assert!("MiB".parse::<Size>().is_err());
+
+        assert_eq!(String::from(Size(100 << 20)), "100MiB");
+        assert_eq!(String::from(Size(2000)), "2KB");
+        assert_eq!(String::from(Size(0)), "0B");
+    }
+
+    #[test]
+    fn parse_age_filter() {
+        let config = Config::parse(
+            r#"
+[rules.node]
+globs = ["/tmp/**/node_modules"]
+older_than = "14d"
+age_by = "atime"
+"#,
+        )
+        .unwrap();
+        let options = &config.rules["node"].options;
+        assert_eq!(options.older_than, Some("14d".parse().unwrap()));
+        assert_eq!(options.age_by, TimeKind::Atime);
+    }
+
+    #[test]
+    fn reject_unknown_rule_keys() {
+        let err = Config::parse(
+            r#"
+[rules.node]
+globs = ["/tmp/**/node_modules"]
+older_then = "30d"
+"#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown key `older_then` in rule `node`"));
+    }
+
+    #[test]
+    fn reject_legacy_content() {
+        assert!(Config::parse("/tmp/a\n").is_err());
+        assert!(Config::parse("trash = true\n").is_err());
+    }
+}
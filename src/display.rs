@@ -1,26 +1,335 @@
-use crate::{path::PathTree, rules::Pattern};
+use crate::{
+    journal::Run,
+    path::{serialize_lossy, SizeMode},
+    preset::Preset,
+    rules::{CleanReport, Pattern},
+};
 use ansi_term::{ANSIString, Color, Style};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use core::fmt;
 use io::Write;
+use serde::Serialize;
 use std::{
     fmt::Display,
     io,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
+/// Output format of the pattern report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("unknown output format `{s}`")),
+        }
+    }
+}
+
 pub(crate) fn format_patterns(
     workdir: &Path,
-    path_tree: &PathTree,
     patterns: &[Pattern],
     absolute_path: bool,
+    size_mode: SizeMode,
 ) -> Result<()> {
     let mut stdout = io::stdout();
-    let total_size = path_tree.get_size().unwrap_or(0);
+    let total_size = total_size(patterns);
 
-    let table = FormatTable::new(patterns, workdir, absolute_path, total_size);
+    let table = FormatTable::new(patterns, workdir, absolute_path, total_size, size_mode);
 
     table.format(&mut stdout)?;
+    write_pinned_note(&mut stdout, patterns)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+pub(crate) fn format_patterns_json(patterns: &[Pattern], size_mode: SizeMode) -> Result<()> {
+    let mut stdout = io::stdout();
+    let report = JsonReport::new(patterns, size_mode);
+
+    serde_json::to_writer_pretty(&mut stdout, &report)?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn pinned_size(patterns: &[Pattern]) -> u64 {
+    patterns.iter().map(|p| p.pinned_size()).sum()
+}
+
+/// Warns about hard linked files which are not freed,
+/// because they are also linked from outside the matched paths.
+fn write_pinned_note(w: &mut impl io::Write, patterns: &[Pattern]) -> io::Result<()> {
+    let pinned_size = pinned_size(patterns);
+    if pinned_size == 0 {
+        return Ok(());
+    }
+    writeln!(
+        w,
+        "  {} of hard linked files are also linked elsewhere and will not be freed",
+        Color::Yellow.paint(SizeUnit::new(pinned_size, true).to_string())
+    )
+}
+
+fn total_size(patterns: &[Pattern]) -> u64 {
+    // the patterns do not overlap, but may not cover the
+    // whole tree due to the size thresholds of the rules
+    patterns
+        .iter()
+        .map(|p| p.get_size_cached().unwrap_or(0))
+        .sum()
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    patterns: Vec<JsonPattern<'a>>,
+    summary: JsonSummary,
+}
+
+#[derive(Serialize)]
+struct JsonPattern<'a> {
+    #[serde(serialize_with = "serialize_lossy")]
+    pattern: &'a Path,
+    rule: &'a str,
+    paths: Vec<JsonPath<'a>>,
+    size: u64,
+    /// Size of hard linked files which are not freed.
+    pinned_size: u64,
+    num_files: usize,
+    num_dirs: usize,
+    /// Fraction of the total size in the range `0..=1`.
+    share: f64,
+}
+
+#[derive(Serialize)]
+struct JsonPath<'a> {
+    #[serde(serialize_with = "serialize_lossy")]
+    path: &'a Path,
+    size: u64,
+    pinned_size: u64,
+}
+
+#[derive(Serialize)]
+struct JsonSummary {
+    size: u64,
+    pinned_size: u64,
+    size_mode: SizeMode,
+    num_files: usize,
+    num_dirs: usize,
+}
+
+impl<'a> JsonReport<'a> {
+    fn new(patterns: &'a [Pattern], size_mode: SizeMode) -> Self {
+        let total_size = total_size(patterns);
+        let patterns: Vec<JsonPattern> = patterns
+            .iter()
+            .map(|p| {
+                let size = p.get_size_cached().unwrap_or(0);
+                JsonPattern {
+                    pattern: p.as_ref(),
+                    rule: p.rule(),
+                    paths: p
+                        .paths()
+                        .iter()
+                        .map(|(path, size)| JsonPath {
+                            path,
+                            size: *size,
+                            pinned_size: p.pinned_size_at(path),
+                        })
+                        .collect(),
+                    size,
+                    pinned_size: p.pinned_size(),
+                    num_files: p.num_files(),
+                    num_dirs: p.num_dirs(),
+                    share: match total_size {
+                        0 => 0.,
+                        _ => size as f64 / total_size as f64,
+                    },
+                }
+            })
+            .collect();
+
+        let summary = JsonSummary {
+            size: total_size,
+            pinned_size: patterns.iter().map(|p| p.pinned_size).sum(),
+            size_mode,
+            num_files: patterns.iter().map(|p| p.num_files).sum(),
+            num_dirs: patterns.iter().map(|p| p.num_dirs).sum(),
+        };
+
+        Self { patterns, summary }
+    }
+}
+
+pub(crate) fn format_dry_run(
+    workdir: &Path,
+    patterns: &[Pattern],
+    absolute_path: bool,
+) -> Result<()> {
+    let mut stdout = io::stdout();
+    let mut total_size = 0;
+    let pinned_size = pinned_size(patterns);
+
+    writeln!(stdout, "\nDry run, the following paths would be removed:")?;
+    for pattern in patterns {
+        for (path, size) in pattern.paths() {
+            let size_fmt = SizeUnit::new(*size, true).to_string();
+            let icon = if path.is_dir() {
+                '\u{f07b}'
+            } else {
+                '\u{f0f6}'
+            };
+            let path = format_path(path, workdir, absolute_path);
+            writeln!(stdout, "  {size_fmt:>9}  {icon}  {}", path.display())?;
+            total_size += size;
+        }
+    }
+
+    write_pinned_note(&mut stdout, patterns)?;
+    let total_size = SizeUnit::new(total_size.saturating_sub(pinned_size), true);
+    write_boxed(
+        &mut stdout,
+        &format!("{total_size} would be freed, nothing was removed"),
+    )?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+/// Prints the bytes freed by a clean run to stdout
+/// and every path which could not be cleaned to stderr.
+pub(crate) fn format_clean_report(report: &CleanReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Table {
+        let mut stdout = io::stdout();
+        writeln!(
+            stdout,
+            "Freed {} of {}",
+            SizeUnit::new(report.freed, true),
+            SizeUnit::new(report.expected, true)
+        )?;
+        stdout.flush()?;
+    }
+
+    let mut stderr = io::stderr();
+    if report.is_cancelled() {
+        writeln!(
+            stderr,
+            "{}",
+            Color::Yellow.paint(format!(
+                "Cancelled: {} paths removed, {} partially removed, {} untouched",
+                report.entries.len(),
+                report.partial.len(),
+                report.untouched.len()
+            ))
+        )?;
+        for entry in &report.entries {
+            writeln!(stderr, "  removed: {}", entry.path.display())?;
+        }
+        for path in &report.partial {
+            writeln!(stderr, "  partially removed: {}", path.display())?;
+        }
+        for path in &report.untouched {
+            writeln!(stderr, "  untouched: {}", path.display())?;
+        }
+    }
+
+    if report.failures.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        stderr,
+        "{}",
+        Color::Red.paint(format!("Failed to clean {} paths:", report.failures.len()))
+    )?;
+    for failure in &report.failures {
+        writeln!(stderr, "  {}: {}", failure.path.display(), failure.error)?;
+    }
+    stderr.flush()?;
+
+    Ok(())
+}
+
+/// Prints a numbered list of items which can be toggled
+/// by the user, each given as its state, size and path.
+pub(crate) fn format_selection(
+    w: &mut impl io::Write,
+    items: &[(bool, u64, &Path)],
+    workdir: &Path,
+    absolute_path: bool,
+) -> Result<()> {
+    for (i, (selected, size, path)) in items.iter().enumerate() {
+        let size_fmt = SizeUnit::new(*size, true).to_string();
+        let mark = match selected {
+            true => Color::Green.paint("[x]"),
+            false => Style::new().dimmed().paint("[ ]"),
+        };
+        let path = format_path(path, workdir, absolute_path);
+        writeln!(w, "  {:>3} {mark} {size_fmt:>9}  {}", i + 1, path.display())?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn format_history(runs: &[Run]) -> Result<()> {
+    let mut stdout = io::stdout();
+    if runs.is_empty() {
+        write_boxed(&mut stdout, "No clean runs recorded yet")?;
+        return Ok(());
+    }
+
+    writeln!(
+        stdout,
+        "  {}",
+        Style::new()
+            .bold()
+            .paint("Run  Date                 Size       Paths  Recoverable")
+    )?;
+    for run in runs {
+        let size_fmt = SizeUnit::new(run.size(), true).to_string();
+        writeln!(
+            stdout,
+            "  #{:<3} {}  {size_fmt:<9}  {:<5}  {}",
+            run.id,
+            run.timestamp,
+            run.entries.len(),
+            run.num_recoverable()
+        )?;
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+pub(crate) fn format_presets(presets: &[&Preset]) -> Result<()> {
+    let mut stdout = io::stdout();
+    for preset in presets {
+        writeln!(
+            stdout,
+            "{}  {}",
+            Style::new().bold().paint(preset.name),
+            preset.description
+        )?;
+        for rule in preset.rules {
+            writeln!(stdout, "  {}: {}", rule.name, rule.description)?;
+            for glob in rule.globs {
+                writeln!(stdout, "    <root>/{glob}")?;
+            }
+            if let Some(marker) = rule.marker {
+                writeln!(stdout, "    only next to {marker}")?;
+            }
+        }
+    }
     stdout.flush()?;
 
     Ok(())
@@ -36,12 +345,18 @@ struct FormatTable {
 }
 
 impl FormatTable {
-    fn new(patterns: &[Pattern], workdir: &Path, absolute_path: bool, total_size: u64) -> Self {
+    fn new(
+        patterns: &[Pattern],
+        workdir: &Path,
+        absolute_path: bool,
+        total_size: u64,
+        size_mode: SizeMode,
+    ) -> Self {
         let num_files = patterns.iter().map(|p| p.num_files()).sum();
         let num_dirs = patterns.iter().map(|p| p.num_dirs()).sum();
 
         let mut entries: Vec<TableEntry> = Vec::with_capacity(patterns.len() + 2);
-        entries.push(TableEntry::heading());
+        entries.push(TableEntry::heading(size_mode));
 
         patterns.iter().for_each(|p| {
             let entry = TableEntry::from_pattern(p, total_size, workdir, absolute_path);
@@ -110,7 +425,7 @@ impl TableEntry {
             Self::format_dirs(pattern.num_dirs()).map(|s| s.into()),
             Self::format_files(pattern.num_files()).map(|s| s.into()),
             Some(
-                format_pattern(pattern, workdir, absolute_path)
+                format_path(pattern.as_ref(), workdir, absolute_path)
                     .to_string_lossy()
                     .to_string()
                     .into(),
@@ -120,11 +435,15 @@ impl TableEntry {
         Self { row }
     }
 
-    fn heading() -> Self {
+    fn heading(size_mode: SizeMode) -> Self {
+        let size = match size_mode {
+            SizeMode::Apparent => "Size",
+            SizeMode::Disk => "Disk",
+        };
         Self {
             row: [
                 Some(Style::new().bold().paint("Share")),
-                Some(Style::new().bold().paint("Size")),
+                Some(Style::new().bold().paint(size)),
                 Some(Style::new().bold().paint("Dirs")),
                 Some(Style::new().bold().paint("Files")),
                 Some(Style::new().bold().paint("Path")),
@@ -220,8 +539,11 @@ fn chars_count(s: &ANSIString<'_>) -> usize {
     count as usize
 }
 
-fn format_pattern(pattern: &Pattern, workdir: &Path, absolute_path: bool) -> PathBuf {
-    let path = pattern.as_ref();
+pub(crate) fn format_size(size: u64) -> String {
+    SizeUnit::new(size, true).to_string()
+}
+
+fn format_path(path: &Path, workdir: &Path, absolute_path: bool) -> PathBuf {
     if absolute_path {
         return path.to_owned();
     }
@@ -325,7 +647,7 @@ impl Display for SizeUnit {
         } else if val < 100 {
             write!(f, "{:.1}{unit}{i}B", val as f64)
         } else {
-            return write!(f, "{val}{unit}{i}B");
+            write!(f, "{val}{unit}{i}B")
         }
     }
 }
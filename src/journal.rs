@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use crate::path::data_home;
+use crate::trash::local_time_now;
+
+/// Append-only log of all clean runs, stored as one JSON object per line.
+pub(crate) struct Journal {
+    file_path: PathBuf,
+}
+
+/// A single clean run together with all the paths it removed.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Run {
+    pub(crate) id: u64,
+    pub(crate) timestamp: String,
+    pub(crate) entries: Vec<Entry>,
+}
+
+/// A path removed during a clean run.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub(crate) pattern: String,
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    /// Where the path went, if it was moved to the trash.
+    pub(crate) location: Option<PathBuf>,
+}
+
+impl Entry {
+    /// Returns `true` if the path can still be put back.
+    pub(crate) fn is_recoverable(&self) -> bool {
+        self.location
+            .as_ref()
+            .is_some_and(|l| fs::symlink_metadata(l).is_ok())
+    }
+}
+
+impl Run {
+    pub(crate) fn size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+
+    pub(crate) fn num_recoverable(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_recoverable()).count()
+    }
+}
+
+impl Journal {
+    /// Opens the journal in the data directory of the current user.
+    pub(crate) fn home() -> Result<Self> {
+        let data_home = data_home().ok_or_else(|| anyhow!("cannot find journal directory"))?;
+        Ok(Self::new(data_home.join("clir").join("journal")))
+    }
+
+    pub(crate) fn new(file_path: PathBuf) -> Self {
+        Self { file_path }
+    }
+
+    /// Reads all runs in chronological order. Lines which cannot be
+    /// parsed, e.g. a record truncated by a crash, are skipped.
+    pub(crate) fn runs(&self) -> Result<Vec<Run>> {
+        let Ok(content) = fs::read_to_string(&self.file_path) else {
+            return Ok(Vec::new());
+        };
+
+        let runs = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .filter_map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|err| {
+                        log::warn!("skipping line {} of {:?}: {err}", i + 1, self.file_path)
+                    })
+                    .ok()
+            })
+            .collect();
+        Ok(runs)
+    }
+
+    /// Returns the run with the given id or the last run if no id is given.
+    pub(crate) fn get(&self, id: Option<u64>) -> Result<Run> {
+        let runs = self.runs()?;
+        match id {
+            Some(id) => runs.into_iter().find(|r| r.id == id),
+            None => runs.into_iter().last(),
+        }
+        .ok_or_else(|| anyhow!("no clean run found in journal"))
+    }
+
+    /// Records a new run and returns its id.
+    pub(crate) fn append(&self, entries: Vec<Entry>) -> Result<u64> {
+        let id = self.runs()?.last().map_or(1, |r| r.id + 1);
+        let run = Run {
+            id,
+            timestamp: local_time_now(),
+            entries,
+        };
+
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent).context("failed to create journal directory")?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&self.file_path)
+            .context("failed to open journal")?;
+        // a record truncated by a crash must not swallow this one
+        let mut record = serde_json::to_string(&run)?;
+        if !ends_with_newline(&mut file)? {
+            record.insert(0, '\n');
+        }
+        writeln!(file, "{record}").context("failed to write journal")?;
+
+        Ok(id)
+    }
+}
+
+fn ends_with_newline(file: &mut File) -> io::Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(true);
+    }
+    let mut last = [0];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
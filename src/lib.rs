@@ -4,18 +4,60 @@ compile_error!(
 );
 
 use crate::cmd::Command;
+use crate::display::OutputFormat;
+use crate::lock::Lock;
+use crate::path::SizeMode;
 use crate::rules::Rules;
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{anyhow, Result};
 use clap::{App, Arg};
 use simple_logger::SimpleLogger;
-use std::{env, path::Path};
+use std::{env, num::NonZeroUsize, path::Path};
 
+mod cache;
+mod cancel;
 mod cmd;
+mod config;
 mod display;
+mod journal;
+mod lock;
 mod path;
+mod preset;
+mod priority;
+mod progress;
+mod protected;
+mod remove;
+mod report;
 mod rules;
+mod select;
+mod trash;
+mod tui;
 
-pub fn run() -> Result<()> {
+/// Outcome of an invocation which did not fail, mapped to the exit code of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Everything was done or there was nothing to do.
+    Success,
+    /// Some paths could not be cleaned.
+    PartialFailure,
+    /// The clean run was aborted before anything was removed.
+    Aborted,
+    /// The clean run was interrupted by SIGINT or SIGTERM.
+    Cancelled,
+}
+
+impl Status {
+    /// Returns the exit code, `1` is used for errors.
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Success => 0,
+            Status::PartialFailure => 2,
+            Status::Aborted => 3,
+            Status::Cancelled => 130,
+        }
+    }
+}
+
+pub fn run() -> Result<Status> {
     #[allow(deprecated)]
     let config_path = env::home_dir()
         .ok_or_else(|| anyhow!("cannot find config file"))?
@@ -32,17 +74,112 @@ pub fn run() -> Result<()> {
                         "One or more paths or patterns. Paths can either be relative or absolute.",
                     )
                     .multiple_values(true),
-            ),
+            )
+                .arg(
+                    Arg::new("rule")
+                        .help("Name of the rule the patterns are added to. Defaults to a new rule per pattern.")
+                        .short('n')
+                        .long("rule")
+                        .action(clap::ArgAction::Set),
+                )
+                .arg(
+                    Arg::new("description")
+                        .help("Describe why the patterns exist")
+                        .short('d')
+                        .long("description")
+                        .action(clap::ArgAction::Set),
+                ),
         )
         .subcommand(
-            App::new("remove").about("Remove paths or patterns").arg(
+            App::new("remove")
+                .about("Remove paths or patterns")
+                .arg(
                 Arg::new("pattern")
                     .help(
                         "One or more paths or patterns. Paths can either be relative or absolute.",
                     )
                     .multiple_values(true),
+            )
+                .arg(
+                    Arg::new("rule")
+                        .help("Remove the whole rule with the given name")
+                        .short('n')
+                        .long("rule")
+                        .conflicts_with("pattern")
+                        .action(clap::ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            App::new("enable").about("Enable rules").arg(
+                Arg::new("rule")
+                    .help("One or more rule names")
+                    .required(true)
+                    .multiple_values(true),
             ),
         )
+        .subcommand(
+            App::new("disable")
+                .about("Disable rules without removing them")
+                .arg(
+                    Arg::new("rule")
+                        .help("One or more rule names")
+                        .required(true)
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand(
+            App::new("preset")
+                .about("Show or add built-in rule sets for common toolchains")
+                .subcommand_required(true)
+                .subcommand(
+                    App::new("show")
+                        .about("Show the rules of presets. Shows all presets by default.")
+                        .arg(Arg::new("preset").multiple_values(true)),
+                )
+                .subcommand(
+                    App::new("add")
+                        .about("Add the rules of presets")
+                        .arg(Arg::new("preset").required(true).multiple_values(true))
+                        .arg(
+                            Arg::new("root")
+                                .help("Directory the rules apply to. Defaults to the current directory.")
+                                .long("root")
+                                .action(clap::ArgAction::Set)
+                                .value_hint(clap::ValueHint::DirPath),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("report")
+                .about("Print one record per matched path")
+                .arg(
+                    Arg::new("format")
+                        .help("Output format of the records")
+                        .long("format")
+                        .action(clap::ArgAction::Set)
+                        .value_parser(["csv", "ndjson"])
+                        .default_value("csv"),
+                ),
+        )
+        .subcommand(App::new("history").about("List past clean runs"))
+        .subcommand(
+            App::new("cache")
+                .about("Manage the cache of directory sizes")
+                .subcommand_required(true)
+                .subcommand(App::new("clear").about("Remove all cached sizes")),
+        )
+        .subcommand(
+            App::new("tui").about("Explore the matched paths and mark them for cleaning"),
+        )
+        .subcommand(
+            App::new("restore")
+                .about("Restore the paths of a past clean run from the trash")
+                .arg(
+                    Arg::new("run-id")
+                        .help("Id of the run as shown by `history`. Defaults to the last run.")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
         .arg(
             Arg::new("config")
                 .help("Path to alternative config file.")
@@ -66,6 +203,55 @@ pub fn run() -> Result<()> {
                 .long("absolute-path")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("format")
+                .help("Output format of the report")
+                .long("format")
+                .action(clap::ArgAction::Set)
+                .value_parser(["table", "json"])
+                .default_value("table"),
+        )
+        .arg(
+            Arg::new("size-mode")
+                .help("Report the apparent size of files or the space they allocate on disk")
+                .long("size-mode")
+                .action(clap::ArgAction::Set)
+                .value_parser(["apparent", "disk"])
+                .default_value("apparent"),
+        )
+        .arg(
+            Arg::new("no-cache")
+                .help("Measure all directories instead of reusing the sizes of unchanged ones")
+                .long("no-cache")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("jobs")
+                .help("Number of threads used to scan and clean. Defaults to one per core.")
+                .short('j')
+                .long("jobs")
+                .action(clap::ArgAction::Set)
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("low-priority")
+                .help("Run with the lowest CPU priority and the idle I/O class")
+                .long("low-priority")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wait")
+                .help("Wait for another clir run on the same config to finish")
+                .long("wait")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-wait")
+                .help("Fail right away if another clir run uses the same config (default)")
+                .long("no-wait")
+                .conflicts_with("wait")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("run")
                 .help("Recursively clean all defined patterns")
@@ -73,6 +259,29 @@ pub fn run() -> Result<()> {
                 .long("run")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("dry-run")
+                .help(
+                    "Only list the matched paths a clean run would remove, not every file \
+                     below them, without writing anything",
+                )
+                .long("dry-run")
+                .requires("run")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trash")
+                .help("Move paths to the trash instead of deleting them permanently")
+                .long("trash")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-trash")
+                .help("Delete paths permanently, even if `trash = true` is configured")
+                .long("no-trash")
+                .conflicts_with("trash")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("confirm")
                 .help("Confirm cleaning all patterns")
@@ -81,29 +290,82 @@ pub fn run() -> Result<()> {
                 .action(clap::ArgAction::SetTrue),
         );
 
-    if let Err(err) = parse_args(&mut app, &current_dir) {
-        app.print_help()?;
-        return Err(err);
+    match parse_args(&mut app, &current_dir) {
+        Err(err) => {
+            app.print_help()?;
+            Err(err)
+        }
+        status => status,
     }
-
-    Ok(())
 }
 
-fn parse_args(app: &mut App, current_dir: &Path) -> Result<()> {
+fn parse_args(app: &mut App, current_dir: &Path) -> Result<Status> {
     let app = app.get_matches_mut();
     let verbosity_level = *app.get_one::<u8>("verbose").unwrap_or(&0);
     let absolute_path = *app.get_one::<bool>("absolute").unwrap_or(&false);
     let config_path = app.get_one::<String>("config").unwrap();
+    let format: OutputFormat = app.get_one::<String>("format").unwrap().parse()?;
+    let size_mode: SizeMode = app.get_one::<String>("size-mode").unwrap().parse()?;
+    let use_cache = !*app.get_one::<bool>("no-cache").unwrap_or(&false);
+    let trash = match (
+        *app.get_one::<bool>("trash").unwrap_or(&false),
+        *app.get_one::<bool>("no-trash").unwrap_or(&false),
+    ) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
 
     setup_logger(verbosity_level);
     log::trace!("working dir: {}", current_dir.display());
 
-    let rules = Rules::new(config_path.as_ref())?;
-    let mut cmd = Command::new(rules, current_dir, absolute_path);
-
     let run = *app.get_one::<bool>("run").unwrap();
     let confirm = *app.get_one::<bool>("confirm").unwrap();
+    let dry_run = *app.get_one::<bool>("dry-run").unwrap();
+
+    // held until the end of the run, so that concurrent runs neither
+    // overwrite each other's rules nor clean the same paths
+    let exclusive = (run && !dry_run)
+        || match app.subcommand() {
+            Some(("preset", p)) => matches!(p.subcommand(), Some(("add", _))),
+            Some((name, _)) => {
+                ["add", "remove", "enable", "disable", "tui", "restore"].contains(&name)
+            }
+            None => false,
+        };
+    // creating or migrating the rules file writes it as well, except in dry runs
+    let writable = exclusive || (!dry_run && Rules::needs_write(config_path.as_ref()));
+    let wait = *app.get_one::<bool>("wait").unwrap_or(&false);
+    let _lock = Lock::acquire(config_path.as_ref(), writable, wait)?;
+    let rules = Rules::new(config_path.as_ref(), writable)?;
+
+    // threads of the pool inherit the priority, so lower it first
+    let low_priority = *app.get_one::<bool>("low-priority").unwrap_or(&false);
+    if low_priority || rules.options().low_priority {
+        if let Err(err) = priority::lower() {
+            log::warn!("failed to lower the priority: {err}");
+        }
+    }
+    let jobs = app.get_one::<u64>("jobs").map(|&jobs| jobs as usize);
+    if let Some(jobs) = jobs.or(rules.options().jobs.map(NonZeroUsize::get)) {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+    let mut cmd = Command::new(
+        rules,
+        current_dir,
+        absolute_path,
+        trash,
+        format,
+        size_mode,
+        use_cache,
+    );
+
     match (run, confirm) {
+        (true, _) if dry_run => {
+            return cmd.dry_run().map(|_| Status::Success);
+        }
         (true, true) => {
             return cmd.clean_all();
         }
@@ -113,13 +375,23 @@ fn parse_args(app: &mut App, current_dir: &Path) -> Result<()> {
         (_, _) => (),
     }
 
-    match app.subcommand() {
+    if let Some(("tui", _)) = app.subcommand() {
+        return cmd.tui();
+    }
+
+    let res = match app.subcommand() {
         Some(("add", p)) => {
             let rules: Vec<&String> = p
                 .get_many("pattern")
                 .ok_or_else(|| anyhow!("invalid patterns for `add`"))?
                 .collect();
-            cmd.add_rules(rules)
+            let name = p.get_one::<String>("rule").map(String::as_str);
+            let description = p.get_one::<String>("description").map(String::as_str);
+            cmd.add_rules(rules, name, description)
+        }
+        Some(("remove", p)) if p.contains_id("rule") => {
+            let names: Vec<&String> = p.get_many("rule").unwrap_or_default().collect();
+            cmd.remove_named_rules(names)
         }
         Some(("remove", p)) => {
             let rules: Vec<&String> = p
@@ -128,8 +400,28 @@ fn parse_args(app: &mut App, current_dir: &Path) -> Result<()> {
                 .collect();
             cmd.remove_rules(rules)
         }
+        Some((enable @ ("enable" | "disable"), p)) => {
+            let names: Vec<&String> = p.get_many("rule").unwrap_or_default().collect();
+            cmd.set_rules_enabled(names, enable == "enable")
+        }
+        Some(("preset", p)) => match p.subcommand() {
+            Some(("add", p)) => {
+                let names: Vec<&String> = p.get_many("preset").unwrap_or_default().collect();
+                cmd.add_presets(names, p.get_one::<String>("root"))
+            }
+            Some((_, p)) => {
+                let names: Vec<&String> = p.get_many("preset").unwrap_or_default().collect();
+                cmd.show_presets(names)
+            }
+            None => unreachable!(),
+        },
+        Some(("report", p)) => cmd.report(p.get_one::<String>("format").unwrap().parse()?),
+        Some(("history", _)) => cmd.history(),
+        Some(("cache", _)) => cmd.clear_cache(),
+        Some(("restore", p)) => cmd.restore(p.get_one::<u64>("run-id").copied()),
         _ => cmd.list().map(|_| ()),
-    }
+    };
+    res.map(|_| Status::Success)
 }
 
 fn setup_logger(verbosity_level: u8) {
@@ -0,0 +1,162 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Advisory lock on a sidecar of the rules file, held until it is dropped.
+/// The rules file itself cannot be locked, since it is replaced on write.
+/// While the lock is held exclusively, the lock file contains the PID of
+/// the process which holds it.
+#[derive(Debug)]
+pub(crate) struct Lock {
+    // closing the file releases the lock
+    file: Option<File>,
+    /// The PID was written and is cleared again on release.
+    written: bool,
+}
+
+impl Lock {
+    /// Locks the rules file at the given path, exclusively for runs which
+    /// change the rules or the file system and shared for all others. If
+    /// a conflicting lock is held, this either waits for it to be released
+    /// or fails right away.
+    pub(crate) fn acquire(config_path: &Path, exclusive: bool, wait: bool) -> Result<Self> {
+        let path = lock_path(config_path);
+        let opened = match exclusive {
+            true => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .map(|file| (file, true)),
+            // runs which only read do not create the lock file, without
+            // it no run can hold the lock exclusively
+            false => match File::open(&path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::none()),
+                opened => opened.map(|file| (file, false)),
+            },
+        };
+        let (mut file, writable) = match opened {
+            Ok(opened) => opened,
+            // e.g. a rules file in a directory of another user, which can still be read
+            Err(err) => match File::open(&path) {
+                Ok(file) => (file, false),
+                Err(_) => {
+                    log::warn!("running without lock, failed to open {path:?}: {err}");
+                    return Ok(Self::none());
+                }
+            },
+        };
+
+        let operation = match exclusive {
+            true => libc::LOCK_EX,
+            false => libc::LOCK_SH,
+        };
+        if let Err(err) = flock(&file, operation | libc::LOCK_NB) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err).with_context(|| format!("failed to lock {path:?}"));
+            }
+
+            let holder = match holder(&path) {
+                Some(pid) => format!("clir process {pid}"),
+                None => "another clir process".to_string(),
+            };
+            if !wait {
+                bail!("{config_path:?} is in use by {holder}, pass `--wait` to wait for it");
+            }
+            eprintln!("Waiting for {holder} to finish...");
+            flock(&file, operation).with_context(|| format!("failed to lock {path:?}"))?;
+        }
+
+        // shared holders would overwrite each other's PID
+        let written = writable && exclusive;
+        if written {
+            file.set_len(0)?;
+            writeln!(file, "{}", process::id())?;
+        }
+        Ok(Self {
+            file: Some(file),
+            written,
+        })
+    }
+
+    fn none() -> Self {
+        Self {
+            file: None,
+            written: false,
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // the next holder may only hold the lock shared and writes no PID
+        if let Some(file) = self.file.as_ref().filter(|_| self.written) {
+            let _ = file.set_len(0);
+        }
+    }
+}
+
+/// Returns the path of the lock file, e.g. `~/.clir.lock` for `~/.clir`.
+fn lock_path(config_path: &Path) -> PathBuf {
+    let mut path = config_path.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+/// Returns the PID written by the process which holds the lock.
+fn holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    loop {
+        // SAFETY: the descriptor stays open for the duration of the call.
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lock;
+    use std::fs;
+
+    #[test]
+    fn lock_is_exclusive() {
+        let root = std::env::temp_dir().join(format!("clir-lock-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let config_path = root.join(".clir");
+
+        // read-only runs do not create the lock file
+        drop(Lock::acquire(&config_path, false, false).unwrap());
+        assert!(!root.join(".clir.lock").exists());
+
+        let lock = Lock::acquire(&config_path, true, false).unwrap();
+        let err = Lock::acquire(&config_path, false, false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("clir process {}", std::process::id())));
+        drop(lock);
+
+        // read-only runs only exclude runs which change something
+        let shared = Lock::acquire(&config_path, false, false).unwrap();
+        assert!(Lock::acquire(&config_path, false, false).is_ok());
+        let err = Lock::acquire(&config_path, true, false).unwrap_err();
+        assert!(err.to_string().contains("another clir process"));
+        drop(shared);
+        assert!(Lock::acquire(&config_path, true, false).is_ok());
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
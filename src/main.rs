@@ -3,8 +3,11 @@ use std::process;
 use clir::run;
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("error: {:#}", e);
-        process::exit(1);
+    match run() {
+        Ok(status) => process::exit(status.code()),
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
     }
 }
@@ -1,14 +1,113 @@
+use crate::cache::SizeCache;
+use crate::config::TimeKind;
+use crate::progress;
 use rayon::prelude::*;
+use serde::{Serialize, Serializer};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    env,
     fs::{self, Metadata},
+    os::unix::fs::MetadataExt,
     path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
 };
 
 #[derive(Debug)]
 pub struct PathTree {
     children: HashMap<PathBuf, PathTree>,
     size: Option<u64>,
+    size_mode: SizeMode,
+    hard_links: HardLinks,
+    /// Size of the hard linked files below a leaf, which are
+    /// also linked from outside the tree and cannot be freed.
+    pinned: HashMap<PathBuf, u64>,
+    /// Leafs which contain at least one link of a hard linked file.
+    linked: HashSet<PathBuf>,
+    cache: Option<Arc<SizeCache>>,
+}
+
+/// Files with more than one link, collected during the size
+/// calculation so that each inode is only counted once.
+#[derive(Debug, Default)]
+pub(crate) struct HardLinks(Mutex<HashMap<(u64, u64), HardLink>>);
+
+#[derive(Debug)]
+struct HardLink {
+    size: u64,
+    nlink: u64,
+    paths: HashSet<PathBuf>,
+}
+
+impl HardLinks {
+    /// Records a link of the file identified by its device and inode.
+    pub(crate) fn insert(&self, path: PathBuf, id: (u64, u64), nlink: u64, size: u64) {
+        let mut links = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        links
+            .entry(id)
+            .or_insert_with(|| HardLink {
+                size,
+                nlink,
+                paths: HashSet::new(),
+            })
+            .paths
+            .insert(path);
+    }
+
+    /// Returns the size of the recorded files, each counted once.
+    pub(crate) fn size(&self) -> u64 {
+        let links = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        links.values().map(|link| link.size).sum()
+    }
+
+    /// Records the links of another collection as well.
+    pub(crate) fn extend(&self, other: &HardLinks) {
+        let other = other.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut links = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for (id, link) in other.iter() {
+            links
+                .entry(*id)
+                .or_insert_with(|| HardLink {
+                    size: link.size,
+                    nlink: link.nlink,
+                    paths: HashSet::new(),
+                })
+                .paths
+                .extend(link.paths.iter().cloned());
+        }
+    }
+
+    /// Returns the first of the recorded paths of each file together with its size.
+    pub(crate) fn first_paths(self) -> Vec<(PathBuf, u64)> {
+        let links = self.0.into_inner().unwrap_or_else(|e| e.into_inner());
+        links
+            .into_values()
+            .filter_map(|link| Some((link.paths.into_iter().min()?, link.size)))
+            .collect()
+    }
+}
+
+/// Determines how the size of a file is measured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeMode {
+    /// The length of the file, i.e. the number of bytes it contains.
+    #[default]
+    Apparent,
+    /// The space allocated on disk, i.e. the number of blocks times 512.
+    Disk,
+}
+
+impl FromStr for SizeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "apparent" => Ok(Self::Apparent),
+            "disk" => Ok(Self::Disk),
+            _ => Err(anyhow::anyhow!("unknown size mode `{s}`")),
+        }
+    }
 }
 
 impl PathTree {
@@ -20,18 +119,137 @@ impl PathTree {
         Self {
             children: HashMap::with_capacity(cap),
             size: None,
+            size_mode: SizeMode::default(),
+            hard_links: HardLinks::default(),
+            pinned: HashMap::new(),
+            linked: HashSet::new(),
+            cache: None,
+        }
+    }
+
+    pub fn with_size_mode(size_mode: SizeMode) -> Self {
+        Self {
+            size_mode,
+            ..Self::new()
         }
     }
 
+    pub fn size_mode(&self) -> SizeMode {
+        self.size_mode
+    }
+
+    /// Reuses the sizes of unchanged directories from the cache.
+    pub(crate) fn with_cache(self, cache: Option<Arc<SizeCache>>) -> Self {
+        Self { cache, ..self }
+    }
+
+    pub(crate) fn cache(&self) -> Option<Arc<SizeCache>> {
+        self.cache.clone()
+    }
+
     /// Inserts a path into the prefix tree and returns the size
     /// if the operation was successful.
     ///
     /// Considers two scenarios:
     /// 1. Ingores paths for which a parent path is already in the tree.
     /// 2. Removes all children if a parent path is inserted.
+    ///
+    /// Hard linked files are left out of the size until
+    /// `resolve_hard_links` is called.
+    #[cfg(test)]
     pub fn insert(&mut self, path: &Path) -> Option<u64> {
-        let calc_size = || get_path_size_par(path, None);
-        self.insert_with(path, calc_size)
+        self.insert_on_fs(path, false)
+    }
+
+    /// Same as `insert`, but the size calculation does not descend
+    /// into other file systems if `one_file_system` is set.
+    pub fn insert_on_fs(&mut self, path: &Path, one_file_system: bool) -> Option<u64> {
+        let size_mode = self.size_mode;
+        let device = one_file_system
+            .then(|| fs::symlink_metadata(path).ok().map(|m| m.dev()))
+            .flatten();
+        let hard_links = std::mem::take(&mut self.hard_links);
+        let cache = self.cache.clone();
+        let calc_size = || {
+            get_path_size_par(
+                path,
+                None,
+                size_mode,
+                Some(&hard_links),
+                device,
+                cache.as_deref(),
+            )
+        };
+        let size = self.insert_with(path, calc_size);
+        self.hard_links = hard_links;
+        size
+    }
+
+    /// Same as `insert_on_fs`, but takes the size and the hard linked files
+    /// of a walk done before, e.g. to check the size against a threshold.
+    pub(crate) fn insert_measured(
+        &mut self,
+        path: &Path,
+        size: u64,
+        links: &HardLinks,
+    ) -> Option<u64> {
+        let hard_links = std::mem::take(&mut self.hard_links);
+        let size = self.insert_with(path, || {
+            hard_links.extend(links);
+            size
+        });
+        self.hard_links = hard_links;
+        size
+    }
+
+    /// Adds the size of each hard linked file once to the leaf which
+    /// contains its first link. Files which are also linked from paths
+    /// outside the tree are recorded as pinned, since removing the
+    /// leaf does not free their space.
+    pub fn resolve_hard_links(&mut self) {
+        let hard_links = std::mem::take(&mut self.hard_links);
+        let links = hard_links.0.into_inner().unwrap_or_else(|e| e.into_inner());
+
+        for link in links.into_values() {
+            let mut paths: Vec<PathBuf> = link.paths.into_iter().collect();
+            paths.sort();
+
+            let mut size = link.size;
+            let mut first_leaf = None;
+            for path in &paths {
+                let Some(leaf) = self.add_size_at(path, size, PathBuf::new()) else {
+                    continue;
+                };
+                size = 0;
+                first_leaf.get_or_insert_with(|| leaf.clone());
+                self.linked.insert(leaf);
+            }
+
+            if let Some(leaf) = first_leaf.filter(|_| (paths.len() as u64) < link.nlink) {
+                log::info!("{:?} is also linked outside of {leaf:?}", paths[0]);
+                *self.pinned.entry(leaf).or_default() += link.size;
+            }
+        }
+    }
+
+    /// Adds the size to the leaf which contains the path and all its
+    /// parents, and returns the path of the leaf.
+    fn add_size_at(&mut self, path: &Path, size: u64, mut leaf: PathBuf) -> Option<PathBuf> {
+        if self.is_leaf() {
+            self.size = self.size.map(|s| s + size);
+            return Some(leaf);
+        }
+
+        let first = path.iter().next()?;
+        leaf.push(first);
+        let leaf = self.children.get_mut(Path::new(first))?.add_size_at(
+            path.strip_prefix(first).ok()?,
+            size,
+            leaf,
+        )?;
+        self.size = Some(self.size.unwrap_or(0) + size);
+
+        Some(leaf)
     }
 
     pub fn insert_with<F: Fn() -> u64>(&mut self, path: &Path, calc_size: F) -> Option<u64> {
@@ -104,6 +322,7 @@ impl PathTree {
         tree.is_leaf()
     }
 
+    #[allow(dead_code)]
     pub fn get_size(&self) -> Option<u64> {
         self.size
     }
@@ -111,28 +330,153 @@ impl PathTree {
     pub fn get_size_at<P: AsRef<Path>>(&self, path: P) -> Option<u64> {
         self.traverse_tree(path)?.size
     }
+
+    /// Returns the size below the path which cannot be freed because
+    /// of hard links outside the tree.
+    pub fn get_pinned_at<P: AsRef<Path>>(&self, path: P) -> u64 {
+        self.pinned.get(path.as_ref()).copied().unwrap_or(0)
+    }
+
+    /// Returns `true` if the leaf at the path contains hard linked files,
+    /// whose size may be counted at another leaf.
+    pub fn contains_links_at<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.linked.contains(path.as_ref())
+    }
+}
+
+/// Returns the size of the path and, for directories, of all
+/// entries in the subtree. Files with more than one link are
+/// passed to `hard_links` instead of being counted, if given.
+/// Entries which are not on the given `device` are skipped.
+/// Symlinks are never followed, their own size is counted.
+/// Directories which are unchanged since they were cached are not read.
+pub(super) fn get_path_size_par<P: AsRef<Path>>(
+    path: P,
+    meta: Option<Metadata>,
+    size_mode: SizeMode,
+    hard_links: Option<&HardLinks>,
+    device: Option<u64>,
+    cache: Option<&SizeCache>,
+) -> u64 {
+    get_path_summary_par(path, meta, size_mode, hard_links, device, cache).size
 }
 
-pub(super) fn get_path_size_par<P: AsRef<Path>>(path: P, meta: Option<Metadata>) -> u64 {
-    let Some(meta) = meta.or_else(|| fs::metadata(&path).ok()) else {
-        return 0;
+/// Size, number of entries and most recent modification of a subtree.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Summary {
+    pub(crate) size: u64,
+    /// Number of files and directories, including the root of the subtree.
+    pub(crate) count: u64,
+    /// Most recent timestamp in seconds since the epoch,
+    /// the modification time unless another kind is asked for.
+    pub(crate) newest: Option<i64>,
+}
+
+impl Summary {
+    pub(crate) fn merge(self, other: Self) -> Self {
+        Self {
+            size: self.size + other.size,
+            count: self.count + other.count,
+            newest: self.newest.max(other.newest),
+        }
+    }
+}
+
+/// Same as `get_path_size_par`, but also counts the entries of the
+/// subtree and finds their most recent modification in the same walk.
+pub(crate) fn get_path_summary_par<P: AsRef<Path>>(
+    path: P,
+    meta: Option<Metadata>,
+    size_mode: SizeMode,
+    hard_links: Option<&HardLinks>,
+    device: Option<u64>,
+    cache: Option<&SizeCache>,
+) -> Summary {
+    get_path_summary_by_par(
+        path,
+        meta,
+        size_mode,
+        hard_links,
+        device,
+        cache,
+        TimeKind::Mtime,
+    )
+}
+
+/// Same as `get_path_summary_par`, but finds the most recent timestamp
+/// of the given kind. Only modification times are cached, so for
+/// other kinds all directories are read.
+pub(crate) fn get_path_summary_by_par<P: AsRef<Path>>(
+    path: P,
+    meta: Option<Metadata>,
+    size_mode: SizeMode,
+    hard_links: Option<&HardLinks>,
+    device: Option<u64>,
+    cache: Option<&SizeCache>,
+    kind: TimeKind,
+) -> Summary {
+    let Some(meta) = meta.or_else(|| fs::symlink_metadata(&path).ok()) else {
+        return Summary::default();
+    };
+    if device.is_some_and(|dev| dev != meta.dev()) {
+        log::info!("skipped {:?} on another file system", path.as_ref());
+        return Summary::default();
+    }
+
+    let size = match size_mode {
+        SizeMode::Apparent if meta.is_dir() => 0,
+        SizeMode::Apparent => meta.len(),
+        SizeMode::Disk => meta.blocks() * 512,
+    };
+    progress::add_scanned(1, size);
+    let time = match kind {
+        TimeKind::Mtime => meta.mtime(),
+        TimeKind::Atime => meta.atime(),
+        TimeKind::Ctime => meta.ctime(),
+    };
+    let summary = Summary {
+        size,
+        count: 1,
+        newest: Some(time),
     };
 
     if meta.is_file() || meta.is_symlink() {
-        return meta.len();
+        if let Some(hard_links) = hard_links.filter(|_| meta.nlink() > 1) {
+            let id = (meta.dev(), meta.ino());
+            hard_links.insert(path.as_ref().to_path_buf(), id, meta.nlink(), size);
+            return Summary { size: 0, ..summary };
+        }
+        return summary;
     }
 
     if meta.is_dir() {
+        let cached = cache
+            .filter(|_| kind == TimeKind::Mtime)
+            .and_then(|c| c.dir_summary(path.as_ref(), &meta, size_mode, hard_links, device));
+        if let Some(summary) = cached {
+            return summary;
+        }
         if let Ok(dir_path) = fs::read_dir(path) {
             return dir_path
                 .par_bridge()
                 .filter_map(|entry| entry.ok())
-                .map(|entry| get_path_size_par(entry.path(), entry.metadata().ok()))
-                .sum();
+                .map(|entry| {
+                    get_path_summary_by_par(
+                        entry.path(),
+                        entry.metadata().ok(),
+                        size_mode,
+                        hard_links,
+                        device,
+                        cache,
+                        kind,
+                    )
+                })
+                .reduce(Summary::default, Summary::merge)
+                .merge(summary);
         }
     }
 
-    0
+    Summary { size: 0, ..summary }
 }
 
 pub(super) fn canonicalize<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
@@ -154,11 +498,44 @@ pub(super) fn canonicalize<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
     Some(components.iter().map(|c| c.as_os_str()).collect())
 }
 
+/// Serializes a path as a string, names which are not valid
+/// UTF-8 are replaced by `U+FFFD` instead of failing.
+pub(crate) fn serialize_lossy<P: AsRef<Path>, S: Serializer>(
+    path: &P,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.as_ref().to_string_lossy())
+}
+
+/// Returns the base directory for user specific data files,
+/// i.e. `$XDG_DATA_HOME` or `~/.local/share`.
+pub(crate) fn data_home() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    env::var_os("XDG_DATA_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".local/share")))
+}
+
+/// Returns the base directory for user specific cached files,
+/// i.e. `$XDG_CACHE_HOME` or `~/.cache`.
+pub(crate) fn cache_home() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".cache")))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::PathTree;
-    use crate::path::canonicalize;
+    use super::{PathTree, SizeMode};
+    use crate::config::TimeKind;
+    use crate::path::{canonicalize, get_path_size_par, get_path_summary_by_par};
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
     use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
 
     #[test]
     fn canonicalize_glob() {
@@ -169,6 +546,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn newest_time_of_subtree() {
+        let root = std::env::temp_dir().join(format!("clir-time-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/old.tmp"), [0]).unwrap();
+        fs::write(root.join("a/b/new.tmp"), [0]).unwrap();
+
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        for path in ["a/old.tmp", "a/b", "a"] {
+            let file = fs::File::open(root.join(path)).unwrap();
+            file.set_modified(old).unwrap();
+        }
+
+        let summary = get_path_summary_by_par(
+            root.join("a"),
+            None,
+            SizeMode::Apparent,
+            None,
+            None,
+            None,
+            TimeKind::Mtime,
+        );
+        let newest = summary.newest.unwrap();
+        let new_mtime = fs::metadata(root.join("a/b/new.tmp"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        assert_eq!(newest, new_mtime);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn size_of_sparse_file() {
+        let path = std::env::temp_dir().join(format!("clir-sparse-{}", std::process::id()));
+        let file = fs::File::create(&path).unwrap();
+        file.set_len(1 << 20).unwrap();
+
+        assert_eq!(
+            get_path_size_par(&path, None, SizeMode::Apparent, None, None, None),
+            1 << 20
+        );
+        assert!(get_path_size_par(&path, None, SizeMode::Disk, None, None, None) < 1 << 20);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn size_on_file_system() {
+        let root = std::env::temp_dir().join(format!("clir-size-fs-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c.tmp"), [0; 8]).unwrap();
+        let device = fs::metadata(&root).unwrap().dev();
+
+        let size = |device| get_path_size_par(&root, None, SizeMode::Apparent, None, device, None);
+        assert_eq!(size(Some(device)), 8);
+        assert_eq!(size(Some(device.wrapping_add(1))), 0);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn hard_links_are_counted_once() {
+        let root = std::env::temp_dir().join(format!("clir-links-{}", std::process::id()));
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::create_dir_all(root.join("c")).unwrap();
+        fs::write(root.join("a/shared"), [0; 8]).unwrap();
+        fs::hard_link(root.join("a/shared"), root.join("b/shared")).unwrap();
+        fs::write(root.join("a/pinned"), [0; 4]).unwrap();
+        fs::hard_link(root.join("a/pinned"), root.join("c/pinned")).unwrap();
+
+        let mut path_tree = PathTree::new();
+        path_tree.insert(&root.join("a"));
+        path_tree.insert(&root.join("b"));
+        path_tree.resolve_hard_links();
+
+        assert_eq!(path_tree.get_size_at(&root), Some(12));
+        assert_eq!(path_tree.get_pinned_at(root.join("a")), 4);
+        assert_eq!(path_tree.get_pinned_at(root.join("b")), 0);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
     #[test]
     fn insert_and_get() {
         let mut path_tree = PathTree::new();
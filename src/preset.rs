@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use crate::config::Rule;
+
+/// Curated set of rules for a common toolchain.
+pub(crate) struct Preset {
+    pub(crate) name: &'static str,
+    pub(crate) description: &'static str,
+    pub(crate) rules: &'static [PresetRule],
+}
+
+pub(crate) struct PresetRule {
+    pub(crate) name: &'static str,
+    pub(crate) description: &'static str,
+    /// Globs relative to the root the preset is installed under.
+    pub(crate) globs: &'static [&'static str],
+    pub(crate) marker: Option<&'static str>,
+}
+
+pub(crate) const PRESETS: &[Preset] = &[
+    Preset {
+        name: "rust",
+        description: "Cargo build artifacts",
+        rules: &[PresetRule {
+            name: "rust-target",
+            description: "cargo build directories",
+            globs: &["**/target"],
+            marker: Some("Cargo.toml"),
+        }],
+    },
+    Preset {
+        name: "node",
+        description: "Node.js dependencies and framework caches",
+        rules: &[
+            PresetRule {
+                name: "node-modules",
+                description: "installed npm packages",
+                globs: &["**/node_modules"],
+                marker: Some("package.json"),
+            },
+            PresetRule {
+                name: "node-next",
+                description: "Next.js build output",
+                globs: &["**/.next"],
+                marker: Some("package.json"),
+            },
+            PresetRule {
+                name: "node-turbo",
+                description: "Turborepo cache",
+                globs: &["**/.turbo"],
+                marker: Some("package.json"),
+            },
+        ],
+    },
+    Preset {
+        name: "python",
+        description: "Python bytecode, test caches and virtual environments",
+        rules: &[
+            PresetRule {
+                name: "python-cache",
+                description: "bytecode and pytest caches",
+                globs: &["**/__pycache__", "**/.pytest_cache"],
+                marker: None,
+            },
+            PresetRule {
+                name: "python-venv",
+                description: "virtual environments",
+                globs: &["**/.venv"],
+                marker: None,
+            },
+        ],
+    },
+    Preset {
+        name: "gradle",
+        description: "Gradle build directories",
+        rules: &[PresetRule {
+            name: "gradle-build",
+            description: "gradle build output",
+            globs: &["**/build"],
+            marker: Some("build.gradle*"),
+        }],
+    },
+    Preset {
+        name: "maven",
+        description: "Maven build directories",
+        rules: &[PresetRule {
+            name: "maven-target",
+            description: "maven build output",
+            globs: &["**/target"],
+            marker: Some("pom.xml"),
+        }],
+    },
+    Preset {
+        name: "cmake",
+        description: "CMake build directories",
+        rules: &[PresetRule {
+            name: "cmake-build",
+            description: "cmake build trees",
+            globs: &["**/build", "**/cmake-build-*"],
+            marker: Some("CMakeLists.txt"),
+        }],
+    },
+    Preset {
+        name: "editor",
+        description: "Editor swap and backup files",
+        rules: &[PresetRule {
+            name: "editor-swap",
+            description: "vim swap files and backup files",
+            globs: &["**/*.swp", "**/*.swo", "**/*~"],
+            marker: None,
+        }],
+    },
+];
+
+pub(crate) fn get(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name)
+}
+
+impl PresetRule {
+    /// Creates the rule with all globs located under the root.
+    pub(crate) fn to_rule(&self, root: &Path) -> Rule {
+        let mut rule = Rule::new(
+            self.description.to_owned(),
+            self.globs.iter().map(|g| root.join(g)).collect(),
+        );
+        rule.options.marker = self.marker.map(str::to_owned);
+        rule
+    }
+}
@@ -0,0 +1,33 @@
+use std::io;
+
+/// The lowest CPU priority, i.e. the highest nice value.
+const NICE: libc::c_int = 19;
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+/// Lowers the CPU priority and, on Linux, moves the process into the idle
+/// I/O scheduling class, so that it only gets disk time no one else needs.
+/// Both apply to the calling thread and are inherited by the threads it
+/// spawns afterwards, so this has to run before the thread pool is started.
+pub(crate) fn lower() -> io::Result<()> {
+    // SAFETY: plain system call without pointers.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        // SAFETY: plain system call without pointers.
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use crossterm::{
+    cursor, execute,
+    style::Print,
+    terminal::{self, ClearType},
+};
+use std::{
+    io::{self, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::display::format_size;
+
+/// How often the progress line is redrawn.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counters updated by the workers while scanning or cleaning,
+/// they are cheap to update whether or not a progress line is shown.
+static PROGRESS: Progress = Progress::new();
+
+struct Progress {
+    /// The pattern currently being scanned.
+    pattern: Mutex<String>,
+    cleaning: AtomicBool,
+    entries: AtomicU64,
+    bytes: AtomicU64,
+    planned: AtomicU64,
+}
+
+impl Progress {
+    const fn new() -> Self {
+        Self {
+            pattern: Mutex::new(String::new()),
+            cleaning: AtomicBool::new(false),
+            entries: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            planned: AtomicU64::new(0),
+        }
+    }
+
+    fn line(&self) -> String {
+        let entries = self.entries.load(Ordering::Relaxed);
+        let bytes = format_size(self.bytes.load(Ordering::Relaxed));
+        if self.cleaning.load(Ordering::Relaxed) {
+            let planned = format_size(self.planned.load(Ordering::Relaxed));
+            return format!("Cleaning: {bytes} of {planned} removed, {entries} entries");
+        }
+
+        let pattern = self.pattern.lock().unwrap_or_else(|e| e.into_inner());
+        format!("Scanning {pattern}: {entries} entries, {bytes}")
+    }
+}
+
+/// Starts counting the entries and bytes of the given pattern.
+pub(crate) fn scanning(pattern: impl ToString) {
+    *PROGRESS.pattern.lock().unwrap_or_else(|e| e.into_inner()) = pattern.to_string();
+}
+
+/// Records entries which were measured, together with their size.
+pub(crate) fn add_scanned(entries: u64, bytes: u64) {
+    PROGRESS.entries.fetch_add(entries, Ordering::Relaxed);
+    PROGRESS.bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Switches to counting removed entries and bytes out of the planned bytes.
+pub(crate) fn cleaning(planned: u64) {
+    PROGRESS.entries.store(0, Ordering::Relaxed);
+    PROGRESS.bytes.store(0, Ordering::Relaxed);
+    PROGRESS.planned.store(planned, Ordering::Relaxed);
+    PROGRESS.cleaning.store(true, Ordering::Relaxed);
+}
+
+/// Records a removed entry, e.g. a file within a removed directory.
+pub(crate) fn add_removed_entry() {
+    PROGRESS.entries.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the freed bytes of a cleaned path.
+pub(crate) fn add_removed(bytes: u64) {
+    PROGRESS.bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Redraws the progress line on stderr until it is dropped, which
+/// clears the line again. Nothing is drawn if stderr is not a terminal.
+pub(crate) struct Reporter {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Reporter {
+    pub(crate) fn start() -> Self {
+        PROGRESS.cleaning.store(false, Ordering::Relaxed);
+        PROGRESS.entries.store(0, Ordering::Relaxed);
+        PROGRESS.bytes.store(0, Ordering::Relaxed);
+
+        if !io::stderr().is_terminal() {
+            return Self {
+                stop: None,
+                handle: None,
+            };
+        }
+
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut drawn = false;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                drawn |= draw(&PROGRESS.line()).is_ok();
+            }
+            if drawn {
+                let _ = draw("");
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        // dropping the sender stops the drawing thread
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn draw(line: &str) -> io::Result<()> {
+    let width = match terminal::size() {
+        Ok((width, _)) if width > 0 => width as usize,
+        _ => 80,
+    };
+    let line: String = line.chars().take(width.saturating_sub(1)).collect();
+    execute!(
+        io::stderr(),
+        cursor::MoveToColumn(0),
+        terminal::Clear(ClearType::CurrentLine),
+        Print(line)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Progress;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn progress_line() {
+        let progress = Progress::new();
+        *progress.pattern.lock().unwrap() = "/tmp/*.log".to_string();
+        progress.entries.store(3, Ordering::Relaxed);
+        progress.bytes.store(2048, Ordering::Relaxed);
+        assert_eq!(progress.line(), "Scanning /tmp/*.log: 3 entries, 2.00KiB");
+
+        progress.cleaning.store(true, Ordering::Relaxed);
+        progress.planned.store(4096, Ordering::Relaxed);
+        assert_eq!(
+            progress.line(),
+            "Cleaning: 2.00KiB of 4.00KiB removed, 3 entries"
+        );
+    }
+}
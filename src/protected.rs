@@ -0,0 +1,140 @@
+use anyhow::{bail, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::path::canonicalize;
+
+/// Paths which are always protected, in addition to `$HOME`
+/// and the mount points of `/` and its direct children.
+const BUILTIN: [&str; 3] = ["/", "/usr", "/etc"];
+
+/// Paths which must never be cleaned, neither themselves
+/// nor by cleaning one of their ancestors.
+#[derive(Debug)]
+pub(crate) struct Protected {
+    paths: Vec<PathBuf>,
+}
+
+impl Protected {
+    /// Collects the built-in protected paths together with
+    /// the ones configured by the user.
+    pub(crate) fn new(configured: &[PathBuf]) -> Self {
+        #[allow(deprecated)]
+        let home = env::home_dir();
+
+        let mut paths: Vec<PathBuf> = BUILTIN
+            .iter()
+            .map(PathBuf::from)
+            .chain(home)
+            .chain(mount_points())
+            .chain(configured.iter().cloned())
+            .filter_map(|path| fs::canonicalize(&path).ok().or_else(|| canonicalize(path)))
+            .collect();
+        paths.sort();
+        paths.dedup();
+
+        Self { paths }
+    }
+
+    /// Returns `true` if the path itself is protected.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.paths.iter().any(|p| p == path)
+    }
+
+    /// Fails if the path is protected or contains a protected path.
+    pub(crate) fn check(&self, path: &Path) -> Result<()> {
+        let Some(protected) = self.paths.iter().find(|p| p.starts_with(path)) else {
+            return Ok(());
+        };
+
+        if protected == path {
+            bail!("{path:?} is protected");
+        }
+        bail!("{path:?} contains the protected path {protected:?}")
+    }
+}
+
+/// Returns the top-level mount points listed in `/proc/self/mounts`,
+/// if available.
+fn mount_points() -> Vec<PathBuf> {
+    fs::read_to_string("/proc/self/mounts")
+        .map(|mounts| top_level_mount_points(&mounts))
+        .unwrap_or_default()
+}
+
+/// Keeps `/` and the mount points directly below it, e.g. `/boot` or `/home`.
+/// Deeper ones, e.g. a bind mount inside a build directory, may be cleaned.
+fn top_level_mount_points(mounts: &str) -> Vec<PathBuf> {
+    mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|mount_point| PathBuf::from(unescape_mount_point(mount_point)))
+        .filter(|path| path.parent().is_none_or(|parent| parent == Path::new("/")))
+        .collect()
+}
+
+/// Decodes the octal escapes of whitespace and backslashes, e.g. `\040`.
+fn unescape_mount_point(mount_point: &str) -> String {
+    let mut unescaped = String::with_capacity(mount_point.len());
+    let mut rest = mount_point;
+    while let Some(i) = rest.find('\\') {
+        unescaped.push_str(&rest[..i]);
+        let code = rest.get(i + 1..i + 4);
+        match code.and_then(|c| u8::from_str_radix(c, 8).ok()) {
+            Some(b) => {
+                unescaped.push(b as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{top_level_mount_points, unescape_mount_point, Protected};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn protected_paths_and_ancestors() {
+        let protected = Protected::new(&[PathBuf::from("/tmp/clir-protected/keep")]);
+
+        assert!(protected.check(Path::new("/")).is_err());
+        assert!(protected.check(Path::new("/usr")).is_err());
+        assert!(protected.check(Path::new("/tmp/clir-protected")).is_err());
+        assert!(protected
+            .check(Path::new("/tmp/clir-protected/keep"))
+            .is_err());
+        assert!(protected.check(Path::new("/usr/share/doc")).is_ok());
+        assert!(protected.contains(Path::new("/usr")));
+        assert!(!protected.contains(Path::new("/tmp/clir-protected")));
+        assert!(protected
+            .check(Path::new("/tmp/clir-protected/keep/target"))
+            .is_ok());
+    }
+
+    #[test]
+    fn only_top_level_mount_points() {
+        let mounts = "/dev/sda1 / ext4 rw 0 0\n\
+                      /dev/sda2 /home ext4 rw 0 0\n\
+                      tmpfs /run/user/1000 tmpfs rw 0 0\n\
+                      tmpfs /home/me/src/mnt tmpfs rw 0 0\n";
+        assert_eq!(
+            top_level_mount_points(mounts),
+            [PathBuf::from("/"), PathBuf::from("/home")]
+        );
+    }
+
+    #[test]
+    fn unescape_mount_points() {
+        assert_eq!(unescape_mount_point("/mnt/a\\040b"), "/mnt/a b");
+        assert_eq!(unescape_mount_point("/mnt/a\\b"), "/mnt/a\\b");
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Component, Path},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::progress;
+
+/// Removes the path and, for directories, all of their entries without
+/// following symlinks. Every entry is opened relative to the file
+/// descriptor of its parent, so that swapping a path component for a
+/// symlink cannot redirect the removal. Only the directory being read is
+/// kept open while descending, so deep trees do not run out of descriptors.
+/// If a device is given, entries on other file systems are left untouched
+/// together with the directories containing them.
+/// Once `cancelled` is set, the removal stops before the next entry and
+/// fails with `ErrorKind::Interrupted`.
+pub(crate) fn remove_all(
+    path: &Path,
+    device: Option<u64>,
+    cancelled: Option<&AtomicBool>,
+) -> io::Result<()> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot remove the root directory",
+        ));
+    };
+    let parent = open_dir(parent)?;
+    let name = c_string(name)?;
+
+    let stat = stat_at(&parent, &name)?;
+    remove_at(parent, &name, &stat, device, cancelled).map(drop)
+}
+
+/// Flags of the directories whose entries are read.
+const READ_FLAGS: libc::c_int =
+    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+
+/// Flags of the directories which are only looked up in, e.g. the ancestors
+/// of the removed path, which unlike reading does not need read permission.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEARCH_FLAGS: libc::c_int =
+    libc::O_PATH | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEARCH_FLAGS: libc::c_int = READ_FLAGS;
+
+/// Opens the absolute path component by component without following symlinks.
+fn open_dir(path: &Path) -> io::Result<OwnedFd> {
+    if !path.is_absolute() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path is not absolute",
+        ));
+    }
+
+    let mut dir = open_at(libc::AT_FDCWD, c"/", SEARCH_FLAGS)?;
+    for component in path.components() {
+        match component {
+            Component::RootDir => continue,
+            Component::Normal(name) => {
+                dir = open_at(dir.as_raw_fd(), &c_string(name)?, SEARCH_FLAGS)?
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "path is not canonical",
+                ))
+            }
+        }
+    }
+
+    Ok(dir)
+}
+
+/// Removes the entry of the directory, for directories including all their
+/// entries. The directory is closed while descending into the entry and is
+/// opened again through `..` afterwards, which is returned together with
+/// whether the entry was removed, i.e. it did not contain another file system.
+fn remove_at(
+    dir: OwnedFd,
+    name: &CStr,
+    stat: &libc::stat,
+    device: Option<u64>,
+    cancelled: Option<&AtomicBool>,
+) -> io::Result<(OwnedFd, bool)> {
+    if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        unlink_at(&dir, name, 0)?;
+        progress::add_removed_entry();
+        return Ok((dir, true));
+    }
+
+    let child = open_at(dir.as_raw_fd(), name, READ_FLAGS)?;
+    // the directory may have been replaced since it was inspected
+    if !same_file(&stat_fd(&child)?, stat) {
+        return Err(io::Error::other(format!(
+            "{name:?} was replaced during removal"
+        )));
+    }
+
+    let parent = stat_fd(&dir)?;
+    drop(dir);
+    let (child, emptied) = remove_entries(child, device, cancelled)?;
+    let dir = open_at(child.as_raw_fd(), c"..", SEARCH_FLAGS)?;
+    if !same_file(&stat_fd(&dir)?, &parent) {
+        return Err(io::Error::other(format!(
+            "{name:?} was moved during removal"
+        )));
+    }
+    drop(child);
+
+    if !emptied {
+        return Ok((dir, false));
+    }
+    unlink_at(&dir, name, libc::AT_REMOVEDIR)?;
+    progress::add_removed_entry();
+    Ok((dir, true))
+}
+
+/// Removes all entries of the directory and returns it together with
+/// whether it is empty now, see `remove_at`.
+fn remove_entries(
+    mut dir: OwnedFd,
+    device: Option<u64>,
+    cancelled: Option<&AtomicBool>,
+) -> io::Result<(OwnedFd, bool)> {
+    let mut emptied = true;
+    for name in read_dir(&dir)? {
+        if cancelled.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let stat = stat_at(&dir, &name)?;
+        if device.is_some_and(|dev| dev != device_of(&stat)) {
+            log::info!("skipped {name:?} on another file system");
+            emptied = false;
+            continue;
+        }
+
+        let removed;
+        (dir, removed) = remove_at(dir, &name, &stat, device, cancelled)?;
+        emptied &= removed;
+    }
+
+    Ok((dir, emptied))
+}
+
+/// Returns the names of all entries of the directory except `.` and `..`.
+fn read_dir(dir: &OwnedFd) -> io::Result<Vec<CString>> {
+    // `fdopendir` takes ownership of the descriptor, so pass a duplicate
+    let fd = dir.try_clone()?;
+    // SAFETY: on success the descriptor is owned by the stream and closed by `closedir`.
+    let stream = unsafe { libc::fdopendir(fd.as_raw_fd()) };
+    if stream.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    std::mem::forget(fd);
+
+    let mut names = Vec::new();
+    loop {
+        // SAFETY: the stream is valid until `closedir` below.
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            break;
+        }
+        // SAFETY: `d_name` is a nul-terminated string within the entry.
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if name != c"." && name != c".." {
+            names.push(name.to_owned());
+        }
+    }
+    // SAFETY: the stream was opened above and is not used afterwards.
+    unsafe { libc::closedir(stream) };
+
+    Ok(names)
+}
+
+fn open_at(dir: RawFd, name: &CStr, flags: libc::c_int) -> io::Result<OwnedFd> {
+    // SAFETY: `name` is a valid nul-terminated string.
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened and is not owned elsewhere.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn stat_at(dir: &OwnedFd, name: &CStr) -> io::Result<libc::stat> {
+    // SAFETY: `stat` is plain old data and only written by `fstatat`.
+    let mut stat = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        libc::fstatat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            &mut stat,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+fn stat_fd(fd: &OwnedFd) -> io::Result<libc::stat> {
+    // SAFETY: `stat` is plain old data and only written by `fstat`.
+    let mut stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+fn unlink_at(dir: &OwnedFd, name: &CStr, flags: libc::c_int) -> io::Result<()> {
+    // SAFETY: `name` is a valid nul-terminated string.
+    if unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn same_file(a: &libc::stat, b: &libc::stat) -> bool {
+    (a.st_dev, a.st_ino) == (b.st_dev, b.st_ino)
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn device_of(stat: &libc::stat) -> u64 {
+    stat.st_dev as u64
+}
+
+fn c_string(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::remove_all;
+    use std::{
+        fs,
+        io::ErrorKind,
+        os::unix::fs::{symlink, MetadataExt},
+        sync::atomic::AtomicBool,
+    };
+
+    #[test]
+    fn remove_without_following_symlinks() {
+        let root = std::env::temp_dir().join(format!("clir-remove-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("outside")).unwrap();
+        fs::write(root.join("a/b/c.tmp"), [0; 8]).unwrap();
+        fs::write(root.join("outside/keep.tmp"), [0; 8]).unwrap();
+        symlink(root.join("outside"), root.join("a/link")).unwrap();
+
+        remove_all(&root.join("a"), None, None).unwrap();
+        assert!(!root.join("a").exists());
+        assert!(root.join("outside/keep.tmp").exists());
+
+        // a symlinked parent is not followed either
+        fs::create_dir_all(root.join("outside/d")).unwrap();
+        symlink(root.join("outside"), root.join("b")).unwrap();
+        assert!(remove_all(&root.join("b/d"), None, None).is_err());
+        assert!(root.join("outside/d").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn stay_on_file_system() {
+        let root = std::env::temp_dir().join(format!("clir-fs-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c.tmp"), [0; 8]).unwrap();
+        let device = fs::metadata(&root).unwrap().dev();
+
+        // entries on other file systems are kept together with their parents
+        remove_all(&root.join("a"), Some(device.wrapping_add(1)), None).unwrap();
+        assert!(root.join("a/b/c.tmp").exists());
+        remove_all(&root.join("a"), Some(device), None).unwrap();
+        assert!(!root.join("a").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn stop_when_cancelled() {
+        let root = std::env::temp_dir().join(format!("clir-cancel-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c.tmp"), [0; 8]).unwrap();
+
+        let cancelled = AtomicBool::new(true);
+        let err = remove_all(&root.join("a"), None, Some(&cancelled)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert!(root.join("a/b/c.tmp").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
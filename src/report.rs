@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::path::{get_path_size_par, serialize_lossy, SizeMode};
+use crate::rules::{Filter, Pattern};
+use crate::trash::format_local_time;
+
+/// Output format of the per-path report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(anyhow!("unknown report format `{s}`")),
+        }
+    }
+}
+
+/// A single matched path of the report.
+#[derive(Serialize)]
+struct PathRecord<'a> {
+    #[serde(serialize_with = "serialize_lossy")]
+    path: &'a Path,
+    #[serde(serialize_with = "serialize_lossy")]
+    pattern: &'a Path,
+    size: u64,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    mtime: Option<String>,
+    /// The path is covered by an overlapping pattern and
+    /// would be removed by that pattern instead.
+    shadowed: bool,
+    /// Size of hard linked files which are also linked from
+    /// outside the matched paths and are not freed.
+    pinned_size: u64,
+    /// The filter of the rule by which the path was dropped,
+    /// such paths are neither listed nor cleaned.
+    filtered: Option<Filter>,
+}
+
+const CSV_HEADER: [&str; 8] = [
+    "path",
+    "pattern",
+    "size",
+    "type",
+    "mtime",
+    "shadowed",
+    "pinned_size",
+    "filtered",
+];
+
+impl<'a> PathRecord<'a> {
+    fn new(
+        path: &'a Path,
+        pattern: &'a Path,
+        size: u64,
+        shadowed: bool,
+        pinned_size: u64,
+        filtered: Option<Filter>,
+    ) -> Self {
+        let meta = fs::symlink_metadata(path).ok();
+        let kind = match &meta {
+            Some(meta) if meta.is_dir() => "dir",
+            Some(meta) if meta.is_symlink() => "symlink",
+            _ => "file",
+        };
+        let mtime = meta.map(|meta| format_local_time(meta.mtime().max(0) as u64));
+
+        Self {
+            path,
+            pattern,
+            size,
+            kind,
+            mtime,
+            shadowed,
+            pinned_size,
+            filtered,
+        }
+    }
+
+    fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        let row = [
+            self.path.to_string_lossy().to_string(),
+            self.pattern.to_string_lossy().to_string(),
+            self.size.to_string(),
+            self.kind.to_owned(),
+            self.mtime.clone().unwrap_or_default(),
+            self.shadowed.to_string(),
+            self.pinned_size.to_string(),
+            self.filtered.map(|f| f.to_string()).unwrap_or_default(),
+        ];
+        write_csv_row(w, &row)
+    }
+}
+
+pub(crate) fn format_report(
+    patterns: &[Pattern],
+    format: ReportFormat,
+    size_mode: SizeMode,
+) -> Result<()> {
+    let records: Vec<PathRecord> = patterns
+        .par_iter()
+        .flat_map_iter(|p| {
+            let paths = p.paths().iter().map(|(path, size)| {
+                PathRecord::new(path, p.as_ref(), *size, false, p.pinned_size_at(path), None)
+            });
+            // shadowed and filtered paths are not part of the size of the pattern
+            let size =
+                |path| get_path_size_par(path, None, size_mode, None, p.device_of(path), None);
+            let shadowed = p
+                .shadowed_paths()
+                .iter()
+                .map(|path| PathRecord::new(path, p.as_ref(), size(path), true, 0, None));
+            let filtered = p.filtered_paths().iter().map(|(path, filter)| {
+                PathRecord::new(path, p.as_ref(), size(path), false, 0, Some(*filter))
+            });
+            paths.chain(shadowed).chain(filtered).collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut stdout = io::stdout().lock();
+    match format {
+        ReportFormat::Csv => {
+            write_csv_row(&mut stdout, &CSV_HEADER)?;
+            for record in &records {
+                record.write_csv(&mut stdout)?;
+            }
+        }
+        ReportFormat::Ndjson => {
+            for record in &records {
+                serde_json::to_writer(&mut stdout, record)?;
+                writeln!(stdout)?;
+            }
+        }
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn write_csv_row<S: AsRef<str>>(w: &mut impl Write, row: &[S]) -> io::Result<()> {
+    let row: Vec<String> = row.iter().map(|f| escape_csv(f.as_ref())).collect();
+    writeln!(w, "{}", row.join(","))
+}
+
+/// Quotes the field if it contains a separator, quote or line break.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_csv;
+
+    #[test]
+    fn escape_csv_fields() {
+        assert_eq!(escape_csv("/tmp/a.rs"), "/tmp/a.rs");
+        assert_eq!(escape_csv("/tmp/a,b"), "\"/tmp/a,b\"");
+        assert_eq!(escape_csv("/tmp/\"a\""), "\"/tmp/\"\"a\"\"\"");
+    }
+}
@@ -1,68 +1,132 @@
-use anyhow::{Context, Result};
-use core::cmp::Eq;
-use core::hash::Hash;
+use anyhow::{anyhow, bail, Context, Result};
 use rayon::prelude::*;
-use std::collections::HashSet;
-use std::convert::From;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fmt;
-use std::fs::{self, OpenOptions};
-use std::io::{BufWriter, Write};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
-use std::string::{ParseError, String};
-use std::time::Instant;
+use std::process;
+use std::string::String;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
-use crate::path::{canonicalize, PathTree};
+use crate::config::{
+    strip_negation, Age, Config, Options, Rule, RuleOptions, Size, Symlinks, NEGATION,
+};
+use crate::journal::Entry;
+use crate::path::{canonicalize, get_path_summary_by_par, HardLinks, PathTree, SizeMode};
+use crate::preset::Preset;
+use crate::progress;
+use crate::protected::Protected;
+use crate::remove::remove_all;
+use crate::trash::Trash;
 
 pub(crate) struct Rules<'a> {
     file_path: &'a Path,
-    collection: HashSet<RawPattern>,
+    config: Config,
 }
 
 impl<'a> Rules<'a> {
-    pub(crate) fn new(file_path: &'a Path) -> Result<Rules<'a>> {
+    /// Loads the rules file. Unless `writable` is set, a missing rules
+    /// file is not created and a legacy one is only migrated in memory.
+    pub(crate) fn new(file_path: &'a Path, writable: bool) -> Result<Rules<'a>> {
         let mut rules = Rules {
             file_path,
-            collection: HashSet::new(),
+            config: Config::default(),
         };
-        rules.load()?;
+        rules.load(writable)?;
 
         Ok(rules)
     }
 
-    fn load(&mut self) -> Result<()> {
-        if let Ok(file_content) = fs::read(self.file_path) {
-            if let Ok(lines) = String::from_utf8(file_content) {
-                for line in lines.split('\n') {
-                    // ignore emtpy lines
-                    if line.is_empty() {
-                        continue;
-                    }
+    /// Returns `true` if loading the rules file writes to it, i.e.
+    /// it does not exist yet or it is a legacy file to be migrated.
+    pub(crate) fn needs_write(file_path: &Path) -> bool {
+        match fs::read_to_string(file_path) {
+            Ok(content) => Config::parse(&content).is_err(),
+            Err(err) => err.kind() == io::ErrorKind::NotFound,
+        }
+    }
 
-                    if let Ok(pattern) = RawPattern::from_str(line) {
-                        self.collection.insert(pattern);
-                    }
-                }
-            } else {
-                anyhow::bail!("failed to parse rules file content")
+    fn load(&mut self, writable: bool) -> Result<()> {
+        let Ok(file_content) = fs::read(self.file_path) else {
+            if !writable {
+                return Ok(());
             }
-
-            Ok(())
-        } else {
             // create empty rules file if not exist
-            fs::write(self.file_path, []).context("failed to create rules file")
+            return self.write().context("failed to create rules file");
+        };
+        let Ok(content) = String::from_utf8(file_content) else {
+            anyhow::bail!("failed to parse rules file content")
+        };
+
+        match Config::parse(&content) {
+            Ok(config) => self.config = config,
+            Err(err) => {
+                // migrate the line-based rules file of older versions
+                log::debug!("{err:#}");
+                self.config = Config::parse_legacy(&content).map_err(|_| err)?;
+                if !writable {
+                    return Ok(());
+                }
+
+                let mut backup = self.file_path.as_os_str().to_owned();
+                backup.push(".bak");
+                fs::copy(self.file_path, &backup).context("failed to back up rules file")?;
+                self.write()?;
+                log::info!("migrated rules file, backup at {backup:?}");
+            }
         }
+
+        Ok(())
     }
 
-    pub(crate) fn add(&mut self, patterns: Vec<String>) -> Result<()> {
-        patterns
+    /// Adds the patterns to the rule with the given name. Without a
+    /// name, each pattern is added as a new rule on its own.
+    pub(crate) fn add(
+        &mut self,
+        patterns: Vec<String>,
+        name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<()> {
+        let globs: Vec<PathBuf> = patterns
             .into_iter()
-            .filter_map(canonicalize)
-            .map(RawPattern::new)
-            .for_each(|p| {
-                self.collection.insert(p);
+            .filter_map(canonicalize_glob)
+            .filter(|glob| !self.config.contains_glob(glob))
+            .fold(Vec::new(), |mut globs, glob| {
+                if !globs.contains(&glob) {
+                    globs.push(glob);
+                }
+                globs
             });
+        let protected = Protected::new(&self.config.options.protected);
+        for glob in &globs {
+            check_glob(glob, &protected)
+                .with_context(|| format!("refusing to add pattern {glob:?}"))?;
+        }
+        let description = description.unwrap_or_default().to_owned();
+
+        match name {
+            Some(name) => {
+                let rule = self
+                    .config
+                    .rules
+                    .entry(name.to_owned())
+                    .or_insert_with(|| Rule::new(String::new(), Vec::new()));
+                rule.globs.extend(globs);
+                if !description.is_empty() {
+                    rule.description = description;
+                }
+            }
+            None => globs.into_iter().for_each(|glob| {
+                let name = self.config.rule_name(&glob);
+                let rule = Rule::new(description.clone(), vec![glob]);
+                self.config.rules.insert(name, rule);
+            }),
+        }
 
         log::info!("rules: {:?}", self.get());
         self.write()?;
@@ -70,52 +134,148 @@ impl<'a> Rules<'a> {
         Ok(())
     }
 
+    /// Installs the rules of the preset under the given root, which must
+    /// not be protected itself, e.g. `/` or the home directory.
+    pub(crate) fn add_preset(&mut self, preset: &Preset, root: &Path) -> Result<()> {
+        let protected = Protected::new(&self.config.options.protected);
+        if protected.contains(root) {
+            bail!("refusing to add preset under the protected path {root:?}");
+        }
+
+        for preset_rule in preset.rules {
+            let rule = preset_rule.to_rule(root);
+            if rule.globs.iter().all(|g| self.config.contains_glob(g)) {
+                log::info!("rule {} is already installed", preset_rule.name);
+                continue;
+            }
+            for glob in &rule.globs {
+                check_glob(glob, &protected)
+                    .with_context(|| format!("refusing to add pattern {glob:?}"))?;
+            }
+            let name = self.config.unique_name(preset_rule.name);
+            self.config.rules.insert(name, rule);
+        }
+
+        self.write()
+    }
+
+    /// Removes the patterns from all rules and drops rules without patterns.
     pub(crate) fn remove(&mut self, patterns: Vec<String>) -> Result<()> {
-        patterns
-            .iter()
-            .filter_map(|p| RawPattern::from_str(p).ok())
-            .for_each(|p| {
-                self.collection.remove(&p);
-            });
+        let globs: Vec<PathBuf> = patterns
+            .into_iter()
+            .map(|p| canonicalize_glob(&p).unwrap_or_else(|| PathBuf::from(p)))
+            .collect();
+
+        self.config.rules.retain(|_, rule| {
+            rule.globs.retain(|g| !globs.contains(g));
+            !rule.globs.is_empty()
+        });
 
         self.write()?;
 
         Ok(())
     }
 
-    pub(crate) fn write(&self) -> Result<()> {
-        fs::remove_file(self.file_path)?;
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(self.file_path)?;
-
-        let mut file_buf = BufWriter::new(file);
-        for r in self.get() {
-            let _n = file_buf.write([r.to_string().as_str(), "\n"].concat().as_bytes())?;
+    /// Removes the rules with the given names.
+    pub(crate) fn remove_named(&mut self, names: Vec<&String>) -> Result<()> {
+        for name in names {
+            self.config
+                .rules
+                .remove(name)
+                .ok_or_else(|| anyhow!("rule `{name}` does not exist"))?;
         }
 
-        file_buf.flush()?;
+        self.write()
+    }
 
-        Ok(())
+    pub(crate) fn set_enabled(&mut self, names: Vec<&String>, enabled: bool) -> Result<()> {
+        for name in names {
+            self.config
+                .rules
+                .get_mut(name)
+                .ok_or_else(|| anyhow!("rule `{name}` does not exist"))?
+                .enabled = enabled;
+        }
+
+        self.write()
+    }
+
+    /// Replaces the rules file through a temporary file next to it, so
+    /// that neither a crash nor a concurrent run sees a partial file.
+    pub(crate) fn write(&self) -> Result<()> {
+        // replace the target of a symlinked rules file, not the symlink
+        let file_path = fs::canonicalize(self.file_path).unwrap_or(self.file_path.to_path_buf());
+        write_atomic(&file_path, self.config.to_toml()?.as_bytes())
+            .with_context(|| format!("failed to write rules file {file_path:?}"))
+    }
+
+    /// Returns the patterns of all enabled rules.
+    pub(crate) fn get(&self) -> Vec<RawPattern<'_>> {
+        self.config
+            .rules
+            .iter()
+            .filter(|(_, rule)| rule.enabled)
+            .flat_map(|(name, rule)| {
+                rule.globs
+                    .iter()
+                    .filter(|glob| strip_negation(glob).is_none())
+                    .map(|glob| RawPattern {
+                        rule: name,
+                        pattern: glob,
+                        options: &rule.options,
+                        one_file_system: rule
+                            .options
+                            .one_file_system
+                            .unwrap_or(self.config.options.one_file_system),
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns the negated globs of all enabled rules, which
+    /// exclude paths from every rule.
+    pub(crate) fn get_excludes(&self) -> Vec<&Path> {
+        self.config
+            .rules
+            .values()
+            .filter(|rule| rule.enabled)
+            .flat_map(|rule| rule.globs.iter().filter_map(|g| strip_negation(g)))
+            .collect()
+    }
+
+    /// Returns the global options of the rules file.
+    pub(crate) fn options(&self) -> &Options {
+        &self.config.options
     }
 
-    pub(crate) fn get(&self) -> Vec<&RawPattern> {
-        self.collection.iter().collect()
+    pub(crate) fn expand_patterns(&self, path_tree: &mut PathTree) -> Vec<Pattern<'_>> {
+        let mut patterns = self.expand_all_patterns(path_tree);
+        patterns.retain(|p| !p.is_empty());
+        patterns
     }
 
-    pub(crate) fn expand_patterns(&self, path_tree: &mut PathTree) -> Vec<Pattern> {
+    /// Same as `expand_patterns`, but also keeps the patterns
+    /// whose paths are all shadowed by other patterns.
+    pub(crate) fn expand_all_patterns(&self, path_tree: &mut PathTree) -> Vec<Pattern<'_>> {
+        let excluded = expand_excludes(self.get_excludes().into_iter());
+
         // patterns can be expanded concurrently
         let patterns: Vec<Pattern> = self
             .get()
             .par_iter()
-            .filter_map(|pattern| pattern.expand_glob())
+            .filter_map(|pattern| {
+                // the filters of a rule measure its matches while expanding
+                progress::scanning(pattern.pattern.display());
+                pattern.expand_glob(&excluded, path_tree.size_mode())
+            })
             .collect();
 
         // insert the paths into the tree
-        patterns
-            .iter()
-            .for_each(|pattern| pattern.insert(path_tree));
+        patterns.iter().for_each(|pattern| {
+            progress::scanning(pattern);
+            pattern.insert(path_tree)
+        });
+        path_tree.resolve_hard_links();
 
         // get the size of the individual patterns after
         // all path are inserted into the tree because
@@ -124,116 +284,459 @@ impl<'a> Rules<'a> {
             .into_iter()
             .par_bridge()
             .map(|p| p.filter_and_get_size(path_tree))
-            .filter(|p| !p.is_empty())
             .collect();
 
         patterns.par_sort_by_key(|p| p.get_size_cached());
         patterns
     }
 
-    pub(crate) fn clean(&self, patterns: &Vec<Pattern>) -> Result<()> {
-        let _n = patterns.par_iter().filter_map(|p| p.clean().ok()).count();
-
+    /// Fails if any path of the patterns is protected.
+    pub(crate) fn check_protected(&self, patterns: &[Pattern]) -> Result<()> {
+        let protected = Protected::new(&self.config.options.protected);
+        for pattern in patterns {
+            for (path, _) in pattern.paths() {
+                protected
+                    .check(path)
+                    .with_context(|| format!("refusing to clean rule `{}`", pattern.rule))?;
+            }
+        }
         Ok(())
     }
+
+    /// Cleans all patterns and returns the removed paths. The `trash` flag
+    /// takes precedence over the rule and global options.
+    pub(crate) fn clean(
+        &self,
+        patterns: &Vec<Pattern>,
+        trash: Option<bool>,
+        cancelled: &AtomicBool,
+    ) -> Result<CleanReport> {
+        self.check_protected(patterns)?;
+
+        let use_trash = |p: &Pattern| {
+            trash
+                .or(p.options.trash)
+                .unwrap_or(self.config.options.trash)
+        };
+        let trash = match patterns.iter().any(use_trash) {
+            true => Some(Trash::home()?),
+            false => None,
+        };
+
+        let planned = patterns
+            .iter()
+            .map(|p| {
+                p.get_size_cached()
+                    .unwrap_or(0)
+                    .saturating_sub(p.pinned_size())
+            })
+            .sum();
+        progress::cleaning(planned);
+        let report = patterns
+            .par_iter()
+            .map(|p| p.clean(trash.as_ref().filter(|_| use_trash(p)), cancelled))
+            .reduce(CleanReport::default, CleanReport::merge);
+
+        Ok(report)
+    }
 }
 
+/// A path which could not be cleaned.
 #[derive(Debug)]
-pub(crate) struct RawPattern {
-    pattern: PathBuf,
+pub(crate) struct Failure {
+    pub(crate) path: PathBuf,
+    pub(crate) error: io::Error,
 }
 
-impl PartialEq for RawPattern {
-    fn eq(&self, other: &Self) -> bool {
-        self.pattern == other.pattern
-    }
+/// Outcome of cleaning a set of patterns.
+#[derive(Debug, Default)]
+pub(crate) struct CleanReport {
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) failures: Vec<Failure>,
+    /// Bytes which would be freed if all paths were cleaned.
+    pub(crate) expected: u64,
+    /// Bytes freed by the paths which were actually cleaned.
+    pub(crate) freed: u64,
+    /// Paths whose removal was cancelled after it started.
+    pub(crate) partial: Vec<PathBuf>,
+    /// Paths which were not touched because the run was cancelled.
+    pub(crate) untouched: Vec<PathBuf>,
 }
 
-impl Eq for RawPattern {}
+impl CleanReport {
+    fn merge(mut self, other: Self) -> Self {
+        self.entries.extend(other.entries);
+        self.failures.extend(other.failures);
+        self.expected += other.expected;
+        self.freed += other.freed;
+        self.partial.extend(other.partial);
+        self.untouched.extend(other.untouched);
+        self
+    }
 
-impl RawPattern {
-    fn new(pattern: PathBuf) -> Self {
-        Self { pattern }
+    /// Returns `true` if the run was cancelled before all paths were cleaned.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        !self.partial.is_empty() || !self.untouched.is_empty()
     }
+}
 
-    pub(crate) fn expand_glob(&self) -> Option<Pattern<'_>> {
+/// A glob pattern of an enabled rule.
+#[derive(Debug)]
+pub(crate) struct RawPattern<'a> {
+    rule: &'a str,
+    pattern: &'a Path,
+    options: &'a RuleOptions,
+    one_file_system: bool,
+}
+
+impl<'a> RawPattern<'a> {
+    /// Expands the glob and subtracts the globally excluded
+    /// paths as well as the excludes of the rule.
+    pub(crate) fn expand_glob(
+        &self,
+        excluded: &[PathBuf],
+        size_mode: SizeMode,
+    ) -> Option<Pattern<'a>> {
         let glob_paths = glob::glob(self.pattern.to_str()?).ok()?;
         let start = Instant::now();
 
-        let paths: Vec<PathBuf> = glob_paths
+        let mut excluded = excluded.to_vec();
+        excluded.extend(expand_excludes(
+            self.options.exclude.iter().map(PathBuf::as_path),
+        ));
+
+        let mut paths: Vec<PathBuf> = Vec::new();
+        let mut filtered = Vec::new();
+        let mut measured = HashMap::new();
+        for path in glob_paths
             .flatten()
-            .filter_map(|path| fs::canonicalize(path).ok())
-            .collect();
+            .filter_map(|path| self.resolve_symlink(&path))
+        {
+            if !self.has_marker(&path) {
+                filtered.push((path, Filter::Marker));
+                continue;
+            }
+            if excluded.iter().any(|e| path.starts_with(e)) {
+                log::info!("excluded path {path:?}");
+                continue;
+            }
+
+            // the filters apply to the whole match, and the size of a
+            // match which is not split by the excludes is inserted as is
+            let size = match self.measure_filtered(&path, size_mode) {
+                Ok(size) => size,
+                Err(filter) => {
+                    filtered.push((path, filter));
+                    continue;
+                }
+            };
+            let num_paths = paths.len();
+            subtract_excluded(path.clone(), &excluded, &mut paths);
+            if let Some(size) = size.filter(|_| paths[num_paths..] == [path.as_path()]) {
+                measured.insert(path, size);
+            }
+        }
 
         log::trace!(
-            "new pattern {:?}: num_paths: {}, time: {:?}",
+            "new pattern {:?} of rule {}: num_paths: {}, time: {:?}",
             self.pattern,
+            self.rule,
             paths.len(),
             Instant::elapsed(&start)
         );
 
-        Some(Pattern::new(self.pattern.as_path(), paths))
+        let mut pattern = Pattern::new(self.rule, self.pattern, self.options, paths);
+        pattern.one_file_system = self.one_file_system;
+        pattern.filtered = filtered;
+        pattern.measured = measured;
+        Some(pattern)
+    }
+
+    /// Measures the path if the rule has an age or size filter and fails
+    /// with the filter the path does not pass. The size and the newest
+    /// timestamp are taken from the same walk. Rejected paths must never
+    /// enter the path tree, where they would shadow the paths of other rules.
+    /// Files grown in place do not change the cached size of their
+    /// directory, so the directories are read instead.
+    fn measure_filtered(
+        &self,
+        path: &Path,
+        size_mode: SizeMode,
+    ) -> Result<Option<(u64, HardLinks)>, Filter> {
+        let has_threshold = self.options.min_size.is_some() || self.options.max_size.is_some();
+        if self.options.older_than.is_none() && !has_threshold {
+            return Ok(None);
+        }
+
+        let device = self
+            .one_file_system
+            .then(|| fs::symlink_metadata(path).ok().map(|m| m.dev()))
+            .flatten();
+        // hard linked files are left out of the size until the tree is resolved
+        let links = HardLinks::default();
+        let summary = get_path_summary_by_par(
+            path,
+            None,
+            size_mode,
+            Some(&links),
+            device,
+            None,
+            self.options.age_by,
+        );
+
+        if let Some(Age(age)) = self.options.older_than {
+            let threshold = SystemTime::now()
+                .checked_sub(age)
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |t| t.as_secs() as i64);
+            if summary.newest.is_none_or(|t| t >= threshold) {
+                log::info!("skipped {path:?}: changed within {age:?}");
+                return Err(Filter::Age);
+            }
+        }
+
+        let min_size = self.options.min_size.map_or(0, |Size(s)| s);
+        let max_size = self.options.max_size.map_or(u64::MAX, |Size(s)| s);
+        let (size, total_size) = (summary.size, summary.size + links.size());
+        if !(min_size..=max_size).contains(&total_size) {
+            log::info!("skipped {path:?}: size {total_size} is out of range");
+            return Err(Filter::Size);
+        }
+        Ok(Some((size, links)))
+    }
+
+    /// Canonicalizes the path, but only resolves a symlink
+    /// at its end if the rule follows symlinks.
+    fn resolve_symlink(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?;
+        let path = fs::canonicalize(path.parent()?).ok()?.join(name);
+        if !fs::symlink_metadata(&path).ok()?.is_symlink() {
+            return Some(path);
+        }
+
+        match self.options.symlinks {
+            Symlinks::Skip => {
+                log::info!("skipped symlink {path:?}");
+                None
+            }
+            Symlinks::RemoveLink => Some(path),
+            Symlinks::Follow => fs::canonicalize(path).ok(),
+        }
+    }
+
+    /// Checks if the marker of the rule exists next to the path.
+    fn has_marker(&self, path: &Path) -> bool {
+        let Some(marker) = &self.options.marker else {
+            return true;
+        };
+        let Some(parent) = path.parent().and_then(Path::to_str) else {
+            return false;
+        };
+
+        let marker_glob = Path::new(&glob::Pattern::escape(parent)).join(marker);
+        let found = marker_glob
+            .to_str()
+            .and_then(|g| glob::glob(g).ok())
+            .is_some_and(|mut paths| paths.any(|p| p.is_ok_and(|p| p != path)));
+        if !found {
+            log::info!("skipped {path:?}: marker {marker:?} not found");
+        }
+        found
+    }
+}
+
+/// Expands exclusion globs to the canonical paths they match.
+fn expand_excludes<'p>(globs: impl Iterator<Item = &'p Path>) -> Vec<PathBuf> {
+    globs
+        .filter_map(|glob| glob::glob(glob.to_str()?).ok())
+        .flat_map(|paths| paths.flatten())
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect()
+}
+
+/// Drops the path if it is excluded. Directories which contain
+/// excluded paths are replaced by their remaining entries, so that
+/// the excluded paths are neither counted nor removed.
+fn subtract_excluded(path: PathBuf, excluded: &[PathBuf], paths: &mut Vec<PathBuf>) {
+    if excluded.iter().any(|e| path.starts_with(e)) {
+        log::info!("excluded path {path:?}");
+        return;
     }
+    if !excluded.iter().any(|e| e.starts_with(&path)) {
+        paths.push(path);
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(&path) else {
+        return;
+    };
+    entries
+        .flatten()
+        .for_each(|entry| subtract_excluded(entry.path(), excluded, paths));
+}
+
+/// Writes the content to a synced temporary file, which then replaces the
+/// file at the path. The permissions of the replaced file are kept.
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let write = || {
+        let mut file = File::create(&tmp_path)?;
+        if let Ok(meta) = fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })?;
+
+    // persist the rename itself
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
 }
 
-impl Hash for RawPattern {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.pattern.hash(state)
+/// Fails if the glob itself or any of the paths it
+/// currently matches is or contains a protected path.
+fn check_glob(glob: &Path, protected: &Protected) -> Result<()> {
+    if strip_negation(glob).is_some() {
+        return Ok(());
     }
+    protected.check(glob)?;
+
+    let Some(paths) = glob.to_str().and_then(|g| glob::glob(g).ok()) else {
+        return Ok(());
+    };
+    paths
+        .flatten()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .try_for_each(|path| protected.check(&path))
 }
 
-impl fmt::Display for RawPattern {
+/// Canonicalizes a glob while keeping its negation prefix.
+fn canonicalize_glob<P: AsRef<Path>>(glob: P) -> Option<PathBuf> {
+    let Some(glob) = strip_negation(glob.as_ref()) else {
+        return canonicalize(glob);
+    };
+    let mut negated = NEGATION.to_string();
+    negated.push_str(canonicalize(glob)?.to_str()?);
+    Some(negated.into())
+}
+
+impl fmt::Display for RawPattern<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.pattern.to_str().ok_or(fmt::Error {})?)
     }
 }
 
-impl FromStr for RawPattern {
-    type Err = ParseError;
+/// Filter of a rule by which a matched path was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Filter {
+    /// `marker` was not found next to the path.
+    Marker,
+    /// The path was modified more recently than `older_than`.
+    Age,
+    /// The size is not between `min_size` and `max_size`.
+    Size,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let pattern = RawPattern {
-            pattern: PathBuf::from(s),
-        };
-        Ok(pattern)
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Marker => write!(f, "marker"),
+            Self::Age => write!(f, "age"),
+            Self::Size => write!(f, "size"),
+        }
     }
 }
 
 pub(crate) struct Pattern<'a> {
+    rule: &'a str,
     pattern: &'a Path,
-    paths: Vec<PathBuf>,
+    options: &'a RuleOptions,
+    paths: Vec<(PathBuf, u64)>,
+    shadowed: Vec<PathBuf>,
+    /// Matched paths which were dropped by the filters of the rule.
+    filtered: Vec<(PathBuf, Filter)>,
+    /// Paths whose size is partly linked from outside the matched paths.
+    pinned: Vec<(PathBuf, u64)>,
+    /// Paths measured for the age or size filters of the rule,
+    /// with their size and the hard linked files below them.
+    measured: HashMap<PathBuf, (u64, HardLinks)>,
+    /// Some paths contain hard linked files counted by other paths,
+    /// so the pattern must be cleaned even without a size.
+    has_links: bool,
+    /// Sizes are calculated and paths removed without
+    /// descending into other file systems.
+    one_file_system: bool,
     size: Option<u64>,
 }
 
 impl<'a> Pattern<'a> {
-    pub(crate) fn new(pattern: &'a Path, paths: Vec<PathBuf>) -> Self {
+    pub(crate) fn new(
+        rule: &'a str,
+        pattern: &'a Path,
+        options: &'a RuleOptions,
+        paths: Vec<PathBuf>,
+    ) -> Self {
         Self {
+            rule,
             pattern,
-            paths,
+            options,
+            paths: paths.into_iter().map(|path| (path, 0)).collect(),
+            shadowed: Vec::new(),
+            filtered: Vec::new(),
+            pinned: Vec::new(),
+            measured: HashMap::new(),
+            has_links: false,
+            one_file_system: false,
             size: None,
         }
     }
 
+    /// Drops the paths which overlap with other patterns.
     pub(crate) fn filter_and_get_size(mut self, path_tree: &PathTree) -> Self {
-        let mut size = 0;
-        self.paths = self
+        let (paths, shadowed): (Vec<_>, Vec<_>) = self
             .paths
             .into_iter()
-            .filter_map(|path| path_tree.get_size_at(&path).map(|sz| (path, sz)))
-            .map(|(path, sz)| {
-                size += sz;
-                path
-            })
+            .map(|(path, _)| (path_tree.get_size_at(&path), path))
+            .partition(|(sz, _)| sz.is_some());
+
+        // paths without a size are covered by a parent path of another pattern
+        self.shadowed = shadowed.into_iter().map(|(_, path)| path).collect();
+        self.paths = paths
+            .into_iter()
+            .filter_map(|(sz, path)| sz.map(|sz| (path, sz)))
             .collect();
+        self.pinned = self
+            .paths
+            .iter()
+            .map(|(path, _)| (path.clone(), path_tree.get_pinned_at(path)))
+            .filter(|(_, pinned)| *pinned > 0)
+            .collect();
+        self.has_links = self
+            .paths
+            .iter()
+            .any(|(path, _)| path_tree.contains_links_at(path));
 
-        self.size = size.into();
+        self.size = self.paths.iter().map(|(_, sz)| sz).sum::<u64>().into();
         self
     }
 
     pub(crate) fn insert(&self, path_tree: &mut PathTree) {
         let start = Instant::now();
-        self.paths.iter().for_each(|path| {
-            path_tree.insert(path);
+        self.paths.iter().for_each(|(path, _)| {
+            match self.measured.get(path) {
+                Some((size, links)) => path_tree.insert_measured(path, *size, links),
+                None => path_tree.insert_on_fs(path, self.one_file_system),
+            };
         });
 
         log::trace!(
@@ -243,42 +746,177 @@ impl<'a> Pattern<'a> {
         );
     }
 
+    /// Creates a pattern of the same rule which only cleans the
+    /// given paths, e.g. entries of a matched directory.
+    pub(crate) fn with_paths(&self, paths: Vec<(PathBuf, u64)>) -> Pattern<'a> {
+        let pinned = self
+            .pinned
+            .iter()
+            .filter(|(path, _)| paths.iter().any(|(p, _)| p == path))
+            .cloned()
+            .collect();
+        Pattern {
+            rule: self.rule,
+            pattern: self.pattern,
+            options: self.options,
+            size: paths.iter().map(|(_, sz)| sz).sum::<u64>().into(),
+            paths,
+            shadowed: Vec::new(),
+            filtered: Vec::new(),
+            pinned,
+            measured: HashMap::new(),
+            has_links: false,
+            one_file_system: self.one_file_system,
+        }
+    }
+
+    /// Keeps only the paths whose flag at the same index is set.
+    pub(crate) fn retain_paths(&mut self, selected: &[bool]) {
+        let mut selected = selected.iter();
+        self.paths.retain(|_| *selected.next().unwrap_or(&true));
+        self.pinned
+            .retain(|(path, _)| self.paths.iter().any(|(p, _)| p == path));
+        self.size = self.paths.iter().map(|(_, sz)| sz).sum::<u64>().into();
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
-        self.size.map_or(true, |s| s == 0)
+        self.size.is_none_or(|s| s == 0) && !self.has_links
     }
 
     pub(crate) fn get_size_cached(&self) -> Option<u64> {
         self.size
     }
 
+    /// Returns the name of the rule the pattern belongs to.
+    pub(crate) fn rule(&self) -> &str {
+        self.rule
+    }
+
+    /// Returns the matched paths together with their size.
+    pub(crate) fn paths(&self) -> &[(PathBuf, u64)] {
+        &self.paths
+    }
+
+    /// Returns the matched paths which are already covered by other patterns.
+    pub(crate) fn shadowed_paths(&self) -> &[PathBuf] {
+        &self.shadowed
+    }
+
+    /// Returns the matched paths which were dropped by the filters of the rule.
+    pub(crate) fn filtered_paths(&self) -> &[(PathBuf, Filter)] {
+        &self.filtered
+    }
+
+    /// Returns the size of the hard linked files, which are also
+    /// linked from outside the matched paths and are not freed.
+    pub(crate) fn pinned_size(&self) -> u64 {
+        self.pinned.iter().map(|(_, sz)| sz).sum()
+    }
+
+    pub(crate) fn pinned_size_at(&self, path: &Path) -> u64 {
+        self.pinned
+            .iter()
+            .find(|(p, _)| p == path)
+            .map_or(0, |(_, sz)| *sz)
+    }
+
+    /// Returns the device of the path, if the pattern
+    /// must not leave its file system.
+    pub(crate) fn device_of(&self, path: &Path) -> Option<u64> {
+        if !self.one_file_system {
+            return None;
+        }
+        fs::symlink_metadata(path).ok().map(|meta| meta.dev())
+    }
+
     pub(crate) fn num_files(&self) -> usize {
-        self.paths.iter().filter(|p| p.is_file()).count()
+        self.paths
+            .iter()
+            .filter(|(p, _)| fs::symlink_metadata(p).is_ok_and(|m| !m.is_dir()))
+            .count()
     }
 
     pub(crate) fn num_dirs(&self) -> usize {
-        self.paths.iter().filter(|p| p.is_dir()).count()
+        self.paths
+            .iter()
+            .filter(|(p, _)| fs::symlink_metadata(p).is_ok_and(|m| m.is_dir()))
+            .count()
     }
 
-    pub(crate) fn clean(&self) -> Result<()> {
-        for path in &self.paths {
-            if path.is_dir() {
-                if let Err(err) = fs::remove_dir_all(path) {
-                    log::warn!("failed to remove directory {path:?}: {err}");
-                    continue;
+    /// Removes all paths of the pattern, or moves them
+    /// to the trash if one is given.
+    /// Cleans the paths one after another and leaves the remaining
+    /// ones untouched once `cancelled` is set.
+    pub(crate) fn clean(&self, trash: Option<&Trash>, cancelled: &AtomicBool) -> CleanReport {
+        let mut report = CleanReport {
+            entries: Vec::with_capacity(self.paths.len()),
+            ..CleanReport::default()
+        };
+        for (path, size) in &self.paths {
+            // hard linked files which are linked elsewhere are not freed
+            let freed = size.saturating_sub(self.pinned_size_at(path));
+            report.expected += freed;
+            if cancelled.load(Ordering::Relaxed) {
+                report.untouched.push(path.clone());
+                continue;
+            }
+
+            let mut location = None;
+            if let Some(trash) = trash {
+                match trash.put(path, self.device_of(path), Some(cancelled)) {
+                    Ok(target) => {
+                        log::info!("moved {path:?} to trash {target:?}");
+                        progress::add_removed_entry();
+                        location = Some(target);
+                    }
+                    Err(_) if cancelled.load(Ordering::Relaxed) => {
+                        log::warn!("cancelled moving {path:?} to trash");
+                        report.untouched.push(path.clone());
+                        continue;
+                    }
+                    Err(error) => {
+                        log::warn!("failed to move {path:?} to trash: {error}");
+                        report.failures.push(Failure {
+                            path: path.clone(),
+                            error,
+                        });
+                        continue;
+                    }
                 }
-                log::info!("removed directory {path:?}");
             } else {
-                if let Err(err) = fs::remove_file(path) {
-                    log::warn!("failed to remove file {path:?}: {err}");
-                    continue;
+                match remove_all(path, self.device_of(path), Some(cancelled)) {
+                    // an `Interrupted` error of the file system is not a cancellation
+                    Err(_) if cancelled.load(Ordering::Relaxed) => {
+                        log::warn!("cancelled removing {path:?}");
+                        report.partial.push(path.clone());
+                        continue;
+                    }
+                    Err(error) => {
+                        log::warn!("failed to remove {path:?}: {error}");
+                        report.failures.push(Failure {
+                            path: path.clone(),
+                            error,
+                        });
+                        continue;
+                    }
+                    Ok(()) => (),
                 }
-                log::info!("removed file {path:?}");
+                log::info!("removed {path:?}");
             }
+
+            report.freed += freed;
+            progress::add_removed(freed);
+            report.entries.push(Entry {
+                pattern: self.to_string(),
+                path: path.clone(),
+                size: *size,
+                location,
+            });
         }
 
         log::trace!("cleaned pattern {self}");
 
-        Ok(())
+        report
     }
 }
 
@@ -293,3 +931,57 @@ impl AsRef<Path> for Pattern<'_> {
         self.pattern
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Pattern, Rules};
+    use crate::config::RuleOptions;
+    use std::{
+        fs,
+        os::unix::fs::{symlink, MetadataExt, PermissionsExt},
+        path::Path,
+        sync::atomic::AtomicBool,
+    };
+
+    #[test]
+    fn clean_with_pinned_size_above_size() {
+        let root = std::env::temp_dir().join(format!("clir-pinned-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("a.tmp");
+        fs::write(&path, [0; 8]).unwrap();
+
+        let options = RuleOptions::default();
+        let mut pattern = Pattern::new("tmp", Path::new("/tmp/*.tmp"), &options, vec![]);
+        // the pinned size comes from another walk than the size of the path
+        pattern.pinned = vec![(path.clone(), 16)];
+        let pattern = pattern.with_paths(vec![(path.clone(), 8)]);
+
+        let report = pattern.clean(None, &AtomicBool::new(false));
+        assert_eq!((report.expected, report.freed), (0, 0));
+        assert!(!path.exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn write_keeps_symlink_and_permissions() {
+        let root = std::env::temp_dir().join(format!("clir-write-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let target = root.join("rules.toml");
+        fs::write(&target, "").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = root.join(".clir");
+        symlink(&target, &link).unwrap();
+
+        let mut rules = Rules::new(&link, true).unwrap();
+        rules
+            .add(vec!["/tmp/clir-write/*.tmp".to_string()], None, None)
+            .unwrap();
+        assert!(link.is_symlink());
+        assert!(fs::read_to_string(&target).unwrap().contains("*.tmp"));
+        assert_eq!(fs::metadata(&target).unwrap().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
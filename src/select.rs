@@ -0,0 +1,191 @@
+use anyhow::Result;
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use crate::display;
+use crate::rules::Pattern;
+
+/// A command entered while selecting patterns or paths.
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    /// Toggles the items with the given zero based indices.
+    Toggle(Vec<usize>),
+    All,
+    None,
+    /// Opens the paths of the pattern with the given zero based index.
+    Paths(usize),
+    Back,
+    Clean,
+    Quit,
+}
+
+impl Input {
+    /// Parses a line of input, numbers are one based like the displayed list.
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim().to_ascii_lowercase();
+        let input = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["a"] => Self::All,
+            ["n"] => Self::None,
+            ["b"] => Self::Back,
+            ["c"] => Self::Clean,
+            ["q"] => Self::Quit,
+            ["p", n] => Self::Paths(n.parse::<usize>().ok()?.checked_sub(1)?),
+            ref numbers => Self::Toggle(
+                numbers
+                    .iter()
+                    .map(|n| n.parse::<usize>().ok()?.checked_sub(1))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|n| !n.is_empty())?,
+            ),
+        };
+        Some(input)
+    }
+}
+
+/// Lets the user pick the patterns, and the paths within each
+/// pattern, which should be cleaned. Returns `None` if the
+/// selection was aborted.
+pub(crate) fn select<'a>(
+    patterns: Vec<Pattern<'a>>,
+    workdir: &Path,
+    absolute_path: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Option<Vec<Pattern<'a>>>> {
+    let mut selected = vec![true; patterns.len()];
+    let mut selected_paths: Vec<Vec<bool>> = patterns
+        .iter()
+        .map(|p| vec![true; p.paths().len()])
+        .collect();
+
+    loop {
+        let items: Vec<(bool, u64, &Path)> = patterns
+            .iter()
+            .zip(&selected)
+            .map(|(p, &sel)| (sel, p.get_size_cached().unwrap_or(0), p.as_ref()))
+            .collect();
+        writeln!(output)?;
+        display::format_selection(output, &items, workdir, absolute_path)?;
+        write!(
+            output,
+            "Toggle patterns by number, (p)aths <n> of a pattern, (a)ll, (n)one, (c)lean or (q)uit: "
+        )?;
+        output.flush()?;
+
+        let Some(line) = read_line(input)? else {
+            return Ok(None);
+        };
+        match Input::parse(&line) {
+            Some(Input::Toggle(indices)) => toggle(&mut selected, &indices),
+            Some(Input::All) => selected.fill(true),
+            Some(Input::None) => selected.fill(false),
+            Some(Input::Paths(i)) if i < patterns.len() => {
+                if !select_paths(
+                    &patterns[i],
+                    &mut selected_paths[i],
+                    workdir,
+                    absolute_path,
+                    input,
+                    output,
+                )? {
+                    return Ok(None);
+                }
+                // picking paths of a pattern implies cleaning it
+                selected[i] = selected_paths[i].contains(&true);
+            }
+            Some(Input::Clean) => break,
+            Some(Input::Quit) => return Ok(None),
+            _ => writeln!(output, "Invalid input: {}", line.trim())?,
+        }
+    }
+
+    let patterns = patterns
+        .into_iter()
+        .zip(selected)
+        .zip(selected_paths)
+        .filter(|((_, sel), _)| *sel)
+        .map(|((mut pattern, _), paths)| {
+            pattern.retain_paths(&paths);
+            pattern
+        })
+        .filter(|pattern| !pattern.paths().is_empty())
+        .collect();
+
+    Ok(Some(patterns))
+}
+
+/// Lets the user toggle the paths of a single pattern. Returns
+/// `false` if the input ended before going back.
+fn select_paths(
+    pattern: &Pattern,
+    selected: &mut [bool],
+    workdir: &Path,
+    absolute_path: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<bool> {
+    loop {
+        let items: Vec<(bool, u64, &Path)> = pattern
+            .paths()
+            .iter()
+            .zip(selected.iter())
+            .map(|((path, size), &sel)| (sel, *size, path.as_path()))
+            .collect();
+        writeln!(output, "\nPaths of {pattern}:")?;
+        display::format_selection(output, &items, workdir, absolute_path)?;
+        write!(output, "Toggle paths by number, (a)ll, (n)one or (b)ack: ")?;
+        output.flush()?;
+
+        let Some(line) = read_line(input)? else {
+            return Ok(false);
+        };
+        match Input::parse(&line) {
+            Some(Input::Toggle(indices)) => toggle(selected, &indices),
+            Some(Input::All) => selected.fill(true),
+            Some(Input::None) => selected.fill(false),
+            Some(Input::Back) => return Ok(true),
+            _ => writeln!(output, "Invalid input: {}", line.trim())?,
+        }
+    }
+}
+
+fn toggle(selected: &mut [bool], indices: &[usize]) {
+    for &i in indices {
+        if let Some(sel) = selected.get_mut(i) {
+            *sel = !*sel;
+        }
+    }
+}
+
+/// Reads a line and returns `None` at the end of the input.
+fn read_line(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{toggle, Input};
+
+    #[test]
+    fn parse_input() {
+        assert_eq!(Input::parse("1 3\n"), Some(Input::Toggle(vec![0, 2])));
+        assert_eq!(Input::parse("p 2"), Some(Input::Paths(1)));
+        assert_eq!(Input::parse(" C "), Some(Input::Clean));
+        assert_eq!(Input::parse("0"), None);
+        assert_eq!(Input::parse("x"), None);
+        assert_eq!(Input::parse(""), None);
+    }
+
+    #[test]
+    fn toggle_items() {
+        let mut selected = vec![true, true, false];
+        toggle(&mut selected, &[0, 2, 7]);
+        assert_eq!(selected, vec![false, true, true]);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::path::data_home;
+use crate::remove::remove_all;
+use anyhow::{anyhow, Context, Result};
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Trash can following the freedesktop.org trash specification,
+/// i.e. trashed paths are moved into `files` and described by a
+/// `.trashinfo` file in `info`.
+#[derive(Debug)]
+pub(crate) struct Trash {
+    files_dir: PathBuf,
+    info_dir: PathBuf,
+}
+
+impl Trash {
+    /// Opens the home trash of the current user and creates
+    /// it if it does not exist yet.
+    pub(crate) fn home() -> Result<Self> {
+        let data_home = data_home().ok_or_else(|| anyhow!("cannot find trash directory"))?;
+
+        Self::new(data_home.join("Trash"))
+    }
+
+    pub(crate) fn new(root: PathBuf) -> Result<Self> {
+        let trash = Self {
+            files_dir: root.join("files"),
+            info_dir: root.join("info"),
+        };
+        fs::create_dir_all(&trash.files_dir).context("failed to create trash directory")?;
+        fs::create_dir_all(&trash.info_dir).context("failed to create trash directory")?;
+
+        Ok(trash)
+    }
+
+    /// Moves the path into the trash and returns its new location. If a
+    /// device is given, entries on other file systems are left in place.
+    /// Once `cancelled` is set, a path which is being copied to another
+    /// file system is left untouched and fails with `ErrorKind::Interrupted`.
+    pub(crate) fn put(
+        &self,
+        path: &Path,
+        device: Option<u64>,
+        cancelled: Option<&AtomicBool>,
+    ) -> io::Result<PathBuf> {
+        let (name, info_path) = self.reserve(path)?;
+        let target = self.files_dir.join(name);
+
+        if let Err(err) = move_path(path, &target, device, cancelled) {
+            // keep the info file of a partially moved path, so that the moved part can be restored
+            if fs::symlink_metadata(&target).is_err() {
+                let _ = fs::remove_file(info_path);
+            }
+            return Err(err);
+        }
+
+        Ok(target)
+    }
+
+    /// Finds an unused name in the trash and claims it by
+    /// atomically creating the corresponding info file.
+    fn reserve(&self, path: &Path) -> io::Result<(OsString, PathBuf)> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+
+        for i in 1.. {
+            let mut name = file_name.to_os_string();
+            if i > 1 {
+                name.push(format!(".{i}"));
+            }
+            if fs::symlink_metadata(self.files_dir.join(&name)).is_ok() {
+                continue;
+            }
+
+            let mut info_name = name.clone();
+            info_name.push(".trashinfo");
+            let info_path = self.info_dir.join(info_name);
+            let mut info_file = match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_path)
+            {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            };
+
+            write!(
+                info_file,
+                "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                encode_path(path),
+                local_time_now()
+            )?;
+
+            return Ok((name, info_path));
+        }
+
+        unreachable!()
+    }
+}
+
+/// Moves a trashed path back to its original location and
+/// removes the corresponding trash info file.
+pub(crate) fn restore(location: &Path, original: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(original).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "original path already exists",
+        ));
+    }
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_path(location, original, None, None)?;
+
+    let info_path = location
+        .parent()
+        .and_then(Path::parent)
+        .zip(location.file_name())
+        .map(|(root, name)| {
+            let mut info_name = name.to_os_string();
+            info_name.push(".trashinfo");
+            root.join("info").join(info_name)
+        });
+    if let Some(info_path) = info_path {
+        let _ = fs::remove_file(info_path);
+    }
+
+    Ok(())
+}
+
+/// Renames the path and falls back to copy and delete if
+/// the target is located on a different file system. Entries
+/// which are not on the given device are neither copied nor removed.
+/// A cancelled copy is removed again, while the removal of a copied
+/// path is always finished.
+fn move_path(
+    from: &Path,
+    to: &Path,
+    device: Option<u64>,
+    cancelled: Option<&AtomicBool>,
+) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            log::info!("moving {from:?} across file systems");
+            if let Err(err) = copy_all(from, to, device, cancelled) {
+                let _ = remove_all(to, None, None);
+                return Err(err);
+            }
+            remove_all(from, device, None)
+        }
+        res => res,
+    }
+}
+
+fn copy_all(
+    from: &Path,
+    to: &Path,
+    device: Option<u64>,
+    cancelled: Option<&AtomicBool>,
+) -> io::Result<()> {
+    if cancelled.is_some_and(|c| c.load(Ordering::Relaxed)) {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+    }
+    let meta = fs::symlink_metadata(from)?;
+    if meta.is_symlink() {
+        return symlink(fs::read_link(from)?, to);
+    }
+
+    if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            let dev = entry.metadata()?.dev();
+            if device.is_some_and(|device| device != dev) {
+                log::info!("skipped {:?} on another file system", entry.path());
+                continue;
+            }
+            copy_all(
+                &entry.path(),
+                &to.join(entry.file_name()),
+                device,
+                cancelled,
+            )?;
+        }
+        return fs::set_permissions(to, meta.permissions());
+    }
+
+    fs::copy(from, to).map(|_| ())
+}
+
+/// Percent-encodes a path as required for the `Path` key of a trash info file.
+fn encode_path(path: &Path) -> String {
+    path.as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Formats the current local time as `YYYY-MM-DDThh:mm:ss`.
+pub(crate) fn local_time_now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_local_time(secs)
+}
+
+pub(crate) fn format_local_time(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    // SAFETY: `tm` is plain old data and `localtime_r` only writes into it.
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        tm
+    };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_all, encode_path, Trash};
+    use std::{fs, io::ErrorKind, os::unix::fs::MetadataExt, path::Path, sync::atomic::AtomicBool};
+
+    #[test]
+    fn encode_special_chars() {
+        assert_eq!(encode_path(Path::new("/tmp/a b%.rs")), "/tmp/a%20b%25.rs");
+    }
+
+    #[test]
+    fn put_with_name_collision() {
+        let root = std::env::temp_dir().join(format!("clir-trash-{}", std::process::id()));
+        let trash = Trash::new(root.join("Trash")).unwrap();
+
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("a/x.tmp"), [0; 8]).unwrap();
+        fs::write(root.join("b/x.tmp"), [0; 8]).unwrap();
+
+        let first = trash.put(&root.join("a/x.tmp"), None, None).unwrap();
+        let second = trash.put(&root.join("b/x.tmp"), None, None).unwrap();
+
+        assert_eq!(first, root.join("Trash/files/x.tmp"));
+        assert_eq!(second, root.join("Trash/files/x.tmp.2"));
+        assert!(root.join("Trash/info/x.tmp.2.trashinfo").is_file());
+        assert!(!root.join("a/x.tmp").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn copy_on_file_system() {
+        let root = std::env::temp_dir().join(format!("clir-copy-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c.tmp"), [0; 8]).unwrap();
+        let device = fs::metadata(&root).unwrap().dev();
+
+        copy_all(
+            &root.join("a"),
+            &root.join("other"),
+            Some(device.wrapping_add(1)),
+            None,
+        )
+        .unwrap();
+        assert!(root.join("other").is_dir());
+        assert!(!root.join("other/b").exists());
+        copy_all(&root.join("a"), &root.join("same"), Some(device), None).unwrap();
+        assert!(root.join("same/b/c.tmp").is_file());
+
+        let cancelled = AtomicBool::new(true);
+        let err = copy_all(
+            &root.join("a"),
+            &root.join("cancelled"),
+            None,
+            Some(&cancelled),
+        );
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::Interrupted);
+        assert!(!root.join("cancelled").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
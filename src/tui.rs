@@ -0,0 +1,590 @@
+use anyhow::Result;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use rayon::prelude::*;
+use std::{
+    cmp::Reverse,
+    fmt, fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use crate::cache::SizeCache;
+use crate::display::format_size;
+use crate::path::{get_path_summary_par, HardLinks, PathTree, SizeMode, Summary};
+use crate::rules::Pattern;
+use crate::trash::format_local_time;
+
+/// Key by which the entries of the explorer are sorted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    /// Largest first.
+    Size,
+    /// Most files and directories first.
+    Count,
+    /// Least recently modified first.
+    Age,
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Size => write!(f, "size"),
+            Self::Count => write!(f, "count"),
+            Self::Age => write!(f, "age"),
+        }
+    }
+}
+
+/// An entry of the explorer, either a pattern or a path below it.
+#[derive(Debug)]
+struct Node {
+    label: String,
+    /// Index of the pattern, only set for the nodes of patterns.
+    pattern: Option<usize>,
+    /// `None` for the nodes of patterns.
+    path: Option<PathBuf>,
+    size: u64,
+    /// Number of files and directories in the subtree,
+    /// counted once the parent is expanded.
+    count: Option<u64>,
+    /// Most recent modification in the subtree.
+    newest: Option<i64>,
+    is_dir: bool,
+    expanded: bool,
+    marked: bool,
+    /// Loaded when the node is expanded for the first time.
+    children: Option<Vec<Node>>,
+    /// Device of the matched path, if the rule must not leave its file system.
+    device: Option<u64>,
+}
+
+impl Node {
+    fn from_pattern(index: usize, pattern: &Pattern) -> Self {
+        // the matched paths were measured by the path tree, they are
+        // only counted once the pattern is expanded
+        let children: Vec<Node> = pattern
+            .paths()
+            .iter()
+            .map(|(path, size)| Self {
+                label: path.to_string_lossy().to_string(),
+                pattern: None,
+                is_dir: fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()),
+                path: Some(path.clone()),
+                size: *size,
+                count: None,
+                newest: None,
+                expanded: false,
+                marked: false,
+                children: None,
+                device: pattern.device_of(path),
+            })
+            .collect();
+
+        Self {
+            label: pattern.to_string(),
+            pattern: Some(index),
+            path: None,
+            size: pattern.get_size_cached().unwrap_or(0),
+            count: None,
+            newest: None,
+            is_dir: true,
+            expanded: false,
+            marked: false,
+            children: Some(children),
+            device: None,
+        }
+    }
+
+    fn from_path(label: String, path: PathBuf, summary: Summary, device: Option<u64>) -> Self {
+        Self {
+            label,
+            pattern: None,
+            is_dir: fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir()),
+            path: Some(path),
+            size: summary.size,
+            count: Some(summary.count),
+            newest: summary.newest,
+            expanded: false,
+            marked: false,
+            children: None,
+            device,
+        }
+    }
+
+    /// Reads the entries of the directory and measures each of them in a
+    /// single walk, unless they are already loaded. The matched paths of
+    /// a pattern are only counted, since their size is already known.
+    fn load_children(&mut self, size_mode: SizeMode, cache: Option<&SizeCache>) {
+        if let Some(children) = &mut self.children {
+            children
+                .par_iter_mut()
+                .filter(|child| child.count.is_none())
+                .for_each(|child| {
+                    let Some(path) = &child.path else {
+                        return;
+                    };
+                    let summary =
+                        get_path_summary_par(path, None, size_mode, None, child.device, cache);
+                    child.count = Some(summary.count);
+                    child.newest = summary.newest;
+                });
+            self.count = Some(children.iter().filter_map(|c| c.count).sum());
+            self.newest = children.iter().filter_map(|c| c.newest).max();
+            return;
+        }
+
+        let (Some(path), true) = (&self.path, self.is_dir) else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(path) else {
+            self.children = Some(Vec::new());
+            return;
+        };
+
+        let device = self.device;
+        let hard_links = HardLinks::default();
+        let mut children: Vec<Node> = entries
+            .par_bridge()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let path = entry.path();
+                let meta = entry.metadata().ok();
+                let summary =
+                    get_path_summary_par(&path, meta, size_mode, Some(&hard_links), device, cache);
+                let label = entry.file_name().to_string_lossy().to_string();
+                Node::from_path(label, path, summary, device)
+            })
+            .collect();
+
+        // hard linked files are counted once, for the entry of their first link
+        for (path, size) in hard_links.first_paths() {
+            let child = children
+                .iter_mut()
+                .find(|c| c.path.as_ref().is_some_and(|p| path.starts_with(p)));
+            if let Some(child) = child {
+                child.size += size;
+            }
+        }
+        self.children = Some(children);
+    }
+
+    fn sort(&mut self, key: SortKey) {
+        if let Some(children) = &mut self.children {
+            sort_nodes(children, key);
+        }
+    }
+
+    /// Collects the marked paths of the subtree with their size.
+    fn marked_paths(&self, paths: &mut Vec<(PathBuf, u64)>) {
+        match (&self.path, self.marked) {
+            (Some(path), true) => paths.push((path.clone(), self.size)),
+            _ => self
+                .children
+                .iter()
+                .flatten()
+                .for_each(|c| c.marked_paths(paths)),
+        }
+    }
+}
+
+fn sort_nodes(nodes: &mut [Node], key: SortKey) {
+    match key {
+        SortKey::Size => nodes.sort_by_key(|n| Reverse(n.size)),
+        SortKey::Count => nodes.sort_by_key(|n| Reverse(n.count)),
+        SortKey::Age => nodes.sort_by_key(|n| n.newest),
+    }
+    nodes.iter_mut().for_each(|n| n.sort(key));
+}
+
+/// State of the explorer.
+struct App<'p, 'a> {
+    patterns: &'p [Pattern<'a>],
+    /// One node per pattern.
+    roots: Vec<Node>,
+    cursor: usize,
+    offset: usize,
+    sort: SortKey,
+    size_mode: SizeMode,
+    cache: Option<Arc<SizeCache>>,
+    /// Waiting for the confirmation to clean the marked paths.
+    confirm: bool,
+}
+
+impl<'p, 'a> App<'p, 'a> {
+    fn new(
+        patterns: &'p [Pattern<'a>],
+        size_mode: SizeMode,
+        cache: Option<Arc<SizeCache>>,
+    ) -> Self {
+        let mut app = Self {
+            patterns,
+            roots: patterns
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Node::from_pattern(i, p))
+                .collect(),
+            cursor: 0,
+            offset: 0,
+            sort: SortKey::Size,
+            size_mode,
+            cache,
+            confirm: false,
+        };
+        app.sort_by(SortKey::Size);
+        app
+    }
+
+    /// Returns the depth and the index path of all visible nodes.
+    fn rows(&self) -> Vec<(usize, Vec<usize>)> {
+        fn visit(nodes: &[Node], index: &mut Vec<usize>, rows: &mut Vec<(usize, Vec<usize>)>) {
+            for (i, node) in nodes.iter().enumerate() {
+                index.push(i);
+                rows.push((index.len() - 1, index.clone()));
+                if node.expanded {
+                    visit(node.children.as_deref().unwrap_or_default(), index, rows);
+                }
+                index.pop();
+            }
+        }
+
+        let mut rows = Vec::new();
+        visit(&self.roots, &mut Vec::new(), &mut rows);
+        rows
+    }
+
+    fn node(&self, index: &[usize]) -> &Node {
+        let mut node = &self.roots[index[0]];
+        for &i in &index[1..] {
+            node = &node.children.as_ref().expect("visible node is loaded")[i];
+        }
+        node
+    }
+
+    fn node_mut(&mut self, index: &[usize]) -> &mut Node {
+        let mut node = &mut self.roots[index[0]];
+        for &i in &index[1..] {
+            node = &mut node.children.as_mut().expect("visible node is loaded")[i];
+        }
+        node
+    }
+
+    fn current(&self) -> Option<Vec<usize>> {
+        self.rows()
+            .into_iter()
+            .nth(self.cursor)
+            .map(|(_, index)| index)
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let len = self.rows().len();
+        self.cursor = self
+            .cursor
+            .saturating_add_signed(delta)
+            .min(len.saturating_sub(1));
+    }
+
+    fn expand(&mut self) {
+        let Some(index) = self.current() else {
+            return;
+        };
+        let (size_mode, sort) = (self.size_mode, self.sort);
+        let cache = self.cache.clone();
+        let node = self.node_mut(&index);
+        if !node.is_dir {
+            return;
+        }
+        if node.expanded {
+            self.move_cursor(1);
+            return;
+        }
+        node.load_children(size_mode, cache.as_deref());
+        node.sort(sort);
+        node.expanded = true;
+    }
+
+    fn collapse(&mut self) {
+        let Some(index) = self.current() else {
+            return;
+        };
+        let node = self.node_mut(&index);
+        if node.expanded {
+            node.expanded = false;
+            return;
+        }
+        // jump to the parent
+        if let Some(parent) = index.get(..index.len() - 1).filter(|p| !p.is_empty()) {
+            self.cursor = self
+                .rows()
+                .iter()
+                .position(|(_, i)| i == parent)
+                .unwrap_or(self.cursor);
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(index) = self.current() {
+            let node = self.node_mut(&index);
+            node.marked = !node.marked;
+        }
+        self.move_cursor(1);
+    }
+
+    fn sort_by(&mut self, key: SortKey) {
+        self.sort = key;
+        let Some(index) = self.current() else {
+            sort_nodes(&mut self.roots, key);
+            return;
+        };
+        // keep the cursor on the same node
+        let path = self.node(&index).path.clone();
+        let label = self.node(&index).label.clone();
+        sort_nodes(&mut self.roots, key);
+        self.cursor = self
+            .rows()
+            .iter()
+            .position(|(_, i)| {
+                let node = self.node(i);
+                node.path == path && node.label == label
+            })
+            .unwrap_or(0);
+    }
+
+    /// Returns the marked paths grouped by their patterns.
+    fn selection(&self) -> Vec<Pattern<'a>> {
+        self.roots
+            .iter()
+            .filter_map(|root| {
+                let pattern = &self.patterns[root.pattern?];
+                let mut paths = Vec::new();
+                match root.marked {
+                    true => paths.extend(pattern.paths().iter().cloned()),
+                    false => root.marked_paths(&mut paths),
+                }
+                (!paths.is_empty()).then(|| pattern.with_paths(paths))
+            })
+            .collect()
+    }
+
+    fn draw(&mut self, w: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let rows = self.rows();
+        let list_height = height.saturating_sub(2).max(1);
+
+        // keep the cursor visible
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + list_height {
+            self.offset = self.cursor + 1 - list_height;
+        }
+
+        let header = format!(
+            "   {:>9} {:>8}  {:<10}  Path (sorted by {})",
+            "Size", "Items", "Modified", self.sort
+        );
+        queue!(
+            w,
+            terminal::Clear(ClearType::All),
+            cursor::MoveTo(0, 0),
+            SetAttribute(Attribute::Bold),
+            Print(truncate(&header, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        for (i, (depth, index)) in rows.iter().enumerate().skip(self.offset).take(list_height) {
+            let node = self.node(index);
+            let line = format_node(node, *depth);
+            queue!(w, cursor::MoveTo(0, (i - self.offset + 1) as u16))?;
+            if i == self.cursor {
+                queue!(w, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                w,
+                Print(truncate(&line, width)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+
+        let selection = self.selection();
+        let num_marked: usize = selection.iter().map(|p| p.paths().len()).sum();
+        let marked_size: u64 = selection.iter().filter_map(|p| p.get_size_cached()).sum();
+        let footer = match self.confirm {
+            true => format!(
+                "Clean {num_marked} marked paths ({})? [y/N]",
+                format_size(marked_size)
+            ),
+            false => format!(
+                "{num_marked} marked ({})  ↑↓ move  →← expand  space mark  s/n/a sort by size/count/age  c clean  q quit",
+                format_size(marked_size)
+            ),
+        };
+        queue!(
+            w,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Bold),
+            Print(truncate(&footer, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        w.flush()
+    }
+}
+
+fn format_node(node: &Node, depth: usize) -> String {
+    let mark = if node.marked { '*' } else { ' ' };
+    let arrow = match (node.is_dir, node.expanded) {
+        (true, true) => '▾',
+        (true, false) => '▸',
+        (false, _) => ' ',
+    };
+    let modified = node
+        .newest
+        .map(|t| format_local_time(t.max(0) as u64))
+        .and_then(|t| t.get(..10).map(str::to_owned))
+        .unwrap_or_default();
+
+    format!(
+        " {mark} {:>9} {:>8}  {modified:<10}  {}{arrow} {}",
+        format_size(node.size),
+        node.count.map(|c| c.to_string()).unwrap_or_default(),
+        "  ".repeat(depth),
+        node.label
+    )
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+/// Restores the terminal when the explorer is left, even on errors.
+struct Screen {
+    stdout: io::Stdout,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self { stdout })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Shows the matched paths of the patterns as an expandable tree and
+/// returns the paths marked for cleaning, or `None` if the user quits.
+/// Expanded directories are measured like the paths of the tree.
+pub(crate) fn explore<'a>(
+    patterns: &[Pattern<'a>],
+    path_tree: &PathTree,
+) -> Result<Option<Vec<Pattern<'a>>>> {
+    let mut app = App::new(patterns, path_tree.size_mode(), path_tree.cache());
+    let mut screen = Screen::enter()?;
+
+    loop {
+        app.draw(&mut screen.stdout)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(None);
+        }
+        if app.confirm {
+            app.confirm = false;
+            if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+                return Ok(Some(app.selection()));
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => app.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.move_cursor(1),
+            KeyCode::PageUp => app.move_cursor(-10),
+            KeyCode::PageDown => app.move_cursor(10),
+            KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => app.expand(),
+            KeyCode::Left | KeyCode::Char('h') => app.collapse(),
+            KeyCode::Char(' ') => app.toggle_mark(),
+            KeyCode::Char('s') => app.sort_by(SortKey::Size),
+            KeyCode::Char('n') => app.sort_by(SortKey::Count),
+            KeyCode::Char('a') => app.sort_by(SortKey::Age),
+            KeyCode::Char('c') if !app.selection().is_empty() => app.confirm = true,
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{App, SortKey};
+    use crate::config::RuleOptions;
+    use crate::path::SizeMode;
+    use crate::rules::Pattern;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn expand_sort_and_mark() {
+        let root = std::env::temp_dir().join(format!("clir-tui-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/small")).unwrap();
+        fs::create_dir_all(root.join("a/large")).unwrap();
+        fs::write(root.join("a/small/x.tmp"), [0; 4]).unwrap();
+        fs::write(root.join("a/large/y.tmp"), [0; 16]).unwrap();
+        // the size of a hard linked file is counted once
+        fs::hard_link(root.join("a/large/y.tmp"), root.join("a/large/z.tmp")).unwrap();
+
+        let options = RuleOptions::default();
+        let glob = root.join("*");
+        let pattern = Pattern::new("tmp", &glob, &options, vec![root.join("a")]);
+        let patterns = [pattern.with_paths(vec![(root.join("a"), 20)])];
+        let mut app = App::new(&patterns, SizeMode::Apparent, None);
+
+        // pattern > a > large, small
+        app.expand();
+        assert_eq!(app.roots[0].count, Some(6));
+        app.move_cursor(1);
+        app.expand();
+        assert_eq!(app.rows().len(), 4);
+        app.move_cursor(1);
+        assert_eq!(app.node(&app.current().unwrap()).label, "large");
+        assert_eq!(app.node(&app.current().unwrap()).size, 16);
+
+        app.sort_by(SortKey::Count);
+        assert_eq!(app.node(&app.current().unwrap()).label, "large");
+        app.move_cursor(1);
+        app.toggle_mark();
+
+        let selection = app.selection();
+        assert_eq!(selection.len(), 1);
+        let paths: Vec<&Path> = selection[0]
+            .paths()
+            .iter()
+            .map(|(p, _)| p.as_path())
+            .collect();
+        assert_eq!(paths, vec![root.join("a/small")]);
+        assert_eq!(selection[0].get_size_cached(), Some(4));
+
+        app.collapse();
+        app.collapse();
+        assert_eq!(app.rows().len(), 2);
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
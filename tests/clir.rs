@@ -1,7 +1,12 @@
 use assert_cmd::prelude::*;
 use mocks::OutputParser;
 use predicates::prelude::*;
-use std::{path::Path, process::Command};
+use std::{
+    fs::File,
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime},
+};
 
 mod mocks;
 
@@ -26,7 +31,7 @@ fn list_patterns() -> anyhow::Result<()> {
         .add_file("test_files/c/e.tmp", 1024)?
         .add_file("test_files/d/f.tmp", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
 
     cmd.arg("-c").arg(mocks.config_path());
     let output = cmd.assert().success();
@@ -64,7 +69,7 @@ fn list_multiple_patterns() -> anyhow::Result<()> {
         .add_file("file1", 1024)?
         .add_file("file2", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
 
     cmd.arg("-c").arg(mocks.config_path());
     let output = cmd.assert().success();
@@ -93,7 +98,7 @@ fn overlapping_patterns() -> anyhow::Result<()> {
         .add_file("test_files/a.tmp", 1024)?
         .add_file("test_files/b.tmp", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
 
     cmd.arg("-c").arg(mocks.config_path());
     let output = cmd.assert().success();
@@ -117,7 +122,7 @@ fn add_pattern() -> anyhow::Result<()> {
         .add_file("test_files/a.tmp", 1024)?
         .add_file("test_files/b.tmp", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
     cmd.arg("-c")
         .arg(mocks.config_path())
         .arg("add")
@@ -127,7 +132,7 @@ fn add_pattern() -> anyhow::Result<()> {
                                                          // not break
     let _ = cmd.assert().success();
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
     cmd.arg("-c").arg(mocks.config_path());
     let output = cmd.assert().success();
     let output = &output.get_output().stdout;
@@ -153,14 +158,14 @@ fn remove_pattern() -> anyhow::Result<()> {
         .add_file("test_files/a.tmp", 1024)?
         .add_file("test_files/b.tmp", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
     cmd.arg("-c")
         .arg(mocks.config_path())
         .arg("remove")
         .arg(mocks.test_dir().join("test_files/b.tmp"));
     let _ = cmd.assert().success();
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
     cmd.arg("-c").arg(mocks.config_path());
     let output = cmd.assert().success();
     let output = &output.get_output().stdout;
@@ -183,7 +188,7 @@ fn clean_patterns_files() -> anyhow::Result<()> {
         .add_file("test_files/a.tmp", 1024)?
         .add_file("test_files/b.tmp", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
 
     cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
     let output = cmd.assert().success();
@@ -216,7 +221,7 @@ fn clean_patterns_dir() -> anyhow::Result<()> {
         .add_file("test_files/a.tmp", 1024)?
         .add_file("test_files/b.tmp", 1024)?;
 
-    let mut cmd = Command::cargo_bin("clir").unwrap();
+    let mut cmd = mocks.command();
 
     cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
     let output = cmd.assert().success();
@@ -235,3 +240,1219 @@ fn clean_patterns_dir() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn dry_run_keeps_files() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files/**/*.tmp"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?
+        .add_file("test_files/b.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .arg("--dry-run")
+        .arg("-a");
+    let output = cmd.assert().success();
+    let stdout = String::from_utf8(output.get_output().stdout.clone())?;
+
+    let a_path = mocks.test_dir().join("test_files/a.tmp");
+    let b_path = mocks.test_dir().join("test_files/b.tmp");
+    assert!(stdout.contains(a_path.to_str().unwrap()));
+    assert!(stdout.contains(b_path.to_str().unwrap()));
+    assert!(stdout.contains("2.00KiB would be freed"));
+
+    assert!(a_path.exists());
+    assert!(b_path.exists());
+
+    // neither the legacy config, the lock nor the size cache are written
+    let config = std::fs::read_to_string(mocks.config_path())?;
+    assert!(config.ends_with("test_files/**/*.tmp\n"));
+    assert!(!mocks.test_dir().join(".clir.bak").exists());
+    assert!(!mocks.test_dir().join(".clir.lock").exists());
+    assert!(!mocks.test_dir().join("clir").exists());
+
+    Ok(())
+}
+
+#[test]
+fn dry_run_refuses_protected_paths() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"[options]
+protected = ["{dir}/test_files/keep"]
+
+[rules.all]
+globs = ["{dir}/test_files"]
+"#,
+        )?
+        .add_dir("test_files")?
+        .add_dir("test_files/keep")?
+        .add_file("test_files/keep/a.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .arg("--dry-run");
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("refusing to clean rule `all`"))
+        .stdout(predicates::str::contains("would be freed").not());
+
+    Ok(())
+}
+
+#[test]
+fn clean_patterns_to_trash() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .arg("-y")
+        .arg("--trash");
+    cmd.assert().success();
+
+    let trash_dir = mocks.test_dir().join("Trash");
+    assert!(!mocks.test_dir().join("test_files").exists());
+    assert!(trash_dir.join("files/test_files/a.tmp").is_file());
+
+    let info = std::fs::read_to_string(trash_dir.join("info/test_files.trashinfo"))?;
+    let original = mocks.test_dir().join("test_files");
+    assert!(info.starts_with("[Trash Info]\n"));
+    assert!(info.contains(&format!("Path={}\n", original.display())));
+    assert!(info.contains("DeletionDate="));
+
+    // the trash can be enabled in the config as well
+    let mocks = mocks
+        .add_dir("other")?
+        .add_file("other/b.tmp", 1024)?
+        .add_config(".clir", vec!["other"])?;
+    let config = std::fs::read_to_string(mocks.config_path())?;
+    std::fs::write(mocks.config_path(), format!("trash = true\n{config}"))?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+
+    assert!(!mocks.test_dir().join("other").exists());
+    assert!(trash_dir.join("files/other/b.tmp").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn one_file_system() -> anyhow::Result<()> {
+    let config = |one_file_system: bool| {
+        format!("[options]\none_file_system = {one_file_system}\n\n[rules.src]\nglobs = [\"{{dir}}/src\"]\n")
+    };
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(&config(false))?
+        .add_dir("src")?
+        .add_file("src/a.tmp", 1024)?;
+    let Some(_mnt) = mocks::Mount::tmpfs(&mocks.test_dir().join("src/mnt")) else {
+        return Ok(());
+    };
+    let mocks = mocks.add_file("src/mnt/keep.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+    assert_pattern_summary!(parser, "2.00KiB", num_dirs = 1, num_files = 0);
+
+    let mocks = mocks.add_raw_config(&config(true))?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+    assert_pattern_summary!(parser, "1.00KiB", num_dirs = 1, num_files = 0);
+
+    // the clean stops at the mount point and keeps it with its parents
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+    assert!(!mocks.test_dir().join("src/a.tmp").exists());
+    assert!(mocks.test_dir().join("src/mnt/keep.tmp").exists());
+
+    Ok(())
+}
+
+#[test]
+fn history_and_restore() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files", "other.tmp"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?
+        .add_file("other.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .arg("-y")
+        .arg("--trash");
+    cmd.assert().success();
+    assert!(!mocks.test_dir().join("test_files").exists());
+    assert!(!mocks.test_dir().join("other.tmp").exists());
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("history");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("#1"))
+        .stdout(predicate::str::contains("2.00KiB"));
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("restore")
+        .arg("1");
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Restored 2 of 2 paths from run #1",
+    ));
+
+    assert!(mocks.test_dir().join("test_files/a.tmp").is_file());
+    assert!(mocks.test_dir().join("other.tmp").is_file());
+    assert!(!mocks
+        .test_dir()
+        .join("Trash/info/other.tmp.trashinfo")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn journal_failures() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["a.tmp", "b.tmp"])?
+        .add_file("a.tmp", 1024)?
+        .add_file("b.tmp", 1024)?
+        // the journal directory cannot be created
+        .add_file("clir", 0)?;
+
+    // the paths are removed, so the run must not fail
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("failed to record the clean run"));
+    assert!(!mocks.test_dir().join("a.tmp").exists());
+
+    // a corrupt journal, e.g. cut off by a crash, still lists the other runs
+    let journal = mocks.test_dir().join("clir/journal");
+    std::fs::remove_file(mocks.test_dir().join("clir"))?;
+    std::fs::create_dir(mocks.test_dir().join("clir"))?;
+    std::fs::write(&journal, "not json\n{\"id\":7,\"timest")?;
+    let mocks = mocks.add_file("a.tmp", 1024)?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("history");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("#1"))
+        .stdout(predicate::str::contains("1.00KiB"));
+    let content = std::fs::read_to_string(&journal)?;
+    assert!(content.lines().last().unwrap().starts_with("{\"id\":1,"));
+
+    Ok(())
+}
+
+#[test]
+fn disabled_rules_are_ignored() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.tmp]
+description = "temporary files"
+globs = ["{dir}/test_files/*.tmp"]
+
+[rules.logs]
+enabled = false
+globs = ["{dir}/test_files/*.log"]
+"#,
+        )?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?
+        .add_file("test_files/b.log", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_entries!(
+        parser,
+        [("test_files/*.tmp", "1.00KiB", num_dirs = 0, num_files = 1)],
+    );
+    assert_pattern_summary!(parser, "1.00KiB", num_dirs = 0, num_files = 1);
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("enable")
+        .arg("logs");
+    cmd.assert().success();
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_summary!(parser, "2.00KiB", num_dirs = 0, num_files = 2);
+
+    Ok(())
+}
+
+#[test]
+fn migrate_legacy_config() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files"])?
+        .add_dir("test_files")?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    cmd.assert().success();
+
+    let config = std::fs::read_to_string(mocks.config_path())?;
+    assert!(config.contains("[rules.test_files]"));
+    assert!(mocks.test_dir().join(".clir.bak").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn failed_config_write_keeps_rules() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new();
+    let dir = mocks.test_dir().join("config");
+    let Some(mnt) = mocks::Mount::tmpfs(&dir) else {
+        return Ok(());
+    };
+    let config_path = dir.join(".clir");
+    let content = "[rules.a]\nglobs = [\"/tmp/a\"]\n";
+    std::fs::write(&config_path, content)?;
+    assert!(mnt.read_only());
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(&config_path).arg("add").arg("/tmp/b");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("failed to write rules file"));
+    assert_eq!(std::fs::read_to_string(&config_path)?, content);
+
+    Ok(())
+}
+
+#[test]
+fn malformed_config_is_not_migrated() -> anyhow::Result<()> {
+    let config = "[options]\ntrsh = true\n\n[rules.a]\nglobs = [\"{dir}/a\"]\n";
+    let mocks = mocks::MockFiles::new().add_raw_config(config)?;
+    let content = std::fs::read_to_string(mocks.config_path())?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("failed to parse rules file"))
+        .stderr(predicate::str::contains("unknown field `trsh`"))
+        .stderr(predicate::str::contains("at line"));
+
+    assert_eq!(std::fs::read_to_string(mocks.config_path())?, content);
+    assert!(!mocks.test_dir().join(".clir.bak").exists());
+
+    // a misspelled filter must not turn into a rule cleaning everything
+    let config = "[rules.a]\nglobs = [\"{dir}/a/*\"]\nmin_sise = \"1GiB\"\n";
+    let mocks = mocks
+        .add_raw_config(config)?
+        .add_dir("a")?
+        .add_file("a/b.tmp", 1024)?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "unknown key `min_sise` in rule `a`",
+    ));
+    assert!(mocks.test_dir().join("a/b.tmp").exists());
+
+    Ok(())
+}
+
+#[test]
+fn excluded_paths() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.target]
+globs = ["{dir}/projects/*/target"]
+exclude = ["{dir}/projects/b/target/keep.tmp"]
+
+[rules.keep]
+globs = ["!{dir}/projects/keep-me/target"]
+"#,
+        )?
+        .add_dir("projects/a/target")?
+        .add_dir("projects/b/target")?
+        .add_dir("projects/keep-me/target")?
+        .add_file("projects/a/target/a.tmp", 1024)?
+        .add_file("projects/b/target/b.tmp", 1024)?
+        .add_file("projects/b/target/keep.tmp", 1024)?
+        .add_file("projects/keep-me/target/c.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_summary!(parser, "2.00KiB", num_dirs = 1, num_files = 1);
+
+    assert!(!mocks.test_dir().join("projects/a/target").exists());
+    assert!(!mocks.test_dir().join("projects/b/target/b.tmp").exists());
+    assert!(mocks.test_dir().join("projects/b/target/keep.tmp").exists());
+    assert!(mocks
+        .test_dir()
+        .join("projects/keep-me/target/c.tmp")
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn add_negated_pattern() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?
+        .add_file("test_files/b.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.current_dir(mocks.test_dir())
+        .arg("-c")
+        .arg(mocks.config_path())
+        .arg("add")
+        .arg("!test_files/b.tmp");
+    cmd.assert().success();
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_summary!(parser, "1.00KiB", num_dirs = 0, num_files = 1);
+
+    Ok(())
+}
+
+#[test]
+fn older_than_filter() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.target]
+globs = ["{dir}/projects/*/target"]
+older_than = "14d"
+"#,
+        )?
+        .add_dir("projects/stale/target/debug")?
+        .add_dir("projects/active/target/debug")?
+        .add_file("projects/stale/target/debug/a.tmp", 1024)?
+        .add_file("projects/active/target/debug/b.tmp", 1024)?
+        .add_file("projects/active/target/debug/c.tmp", 1024)?;
+
+    let old = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+    for path in [
+        "projects/stale/target/debug/a.tmp",
+        "projects/stale/target/debug",
+        "projects/stale/target",
+        "projects/active/target/debug/b.tmp",
+        "projects/active/target/debug",
+        "projects/active/target",
+    ] {
+        File::open(mocks.test_dir().join(path))?.set_modified(old)?;
+    }
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    // c.tmp was modified recently, so the whole active target is kept
+    assert_pattern_summary!(parser, "1.00KiB", num_dirs = 1, num_files = 0);
+
+    Ok(())
+}
+
+#[test]
+fn size_threshold_filter() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.tmp]
+globs = ["{dir}/test_files/*.tmp"]
+min_size = "2KiB"
+max_size = "4KiB"
+"#,
+        )?
+        .add_dir("test_files")?
+        .add_file("test_files/tiny.tmp", 1024)?
+        .add_file("test_files/medium.tmp", 3 * 1024)?
+        .add_file("test_files/large.tmp", 8 * 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_entries!(
+        parser,
+        [("test_files/*.tmp", "3.00KiB", num_dirs = 0, num_files = 1)],
+    );
+    assert_pattern_summary!(parser, "3.00KiB", num_dirs = 0, num_files = 1);
+
+    Ok(())
+}
+
+#[test]
+fn size_threshold_applies_before_excludes() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.build]
+globs = ["{dir}/build"]
+exclude = ["{dir}/build/keep.tmp"]
+min_size = "3KiB"
+"#,
+        )?
+        .add_dir("build")?
+        .add_file("build/a.tmp", 2048)?
+        .add_file("build/keep.tmp", 2048)?;
+
+    // the match has 4KiB, of which only the entries left by the exclude are listed
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+    assert_pattern_summary!(parser, "2.00KiB", num_dirs = 0, num_files = 1);
+
+    Ok(())
+}
+
+#[test]
+fn size_threshold_does_not_shadow_other_rules() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.large]
+globs = ["{dir}/p/*"]
+min_size = "1MiB"
+
+[rules.target]
+globs = ["{dir}/p/x/target"]
+"#,
+        )?
+        .add_dir("p/x/target")?
+        .add_file("p/x/target/a.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_entries!(
+        parser,
+        [("p/x/target", "1.00KiB", num_dirs = 1, num_files = 0)],
+    );
+
+    Ok(())
+}
+
+#[test]
+fn marker_filter() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.rust]
+globs = ["{dir}/projects/*/target"]
+marker = "Cargo.toml"
+"#,
+        )?
+        .add_dir("projects/crate/target")?
+        .add_dir("projects/other/target")?
+        .add_file("projects/crate/Cargo.toml", 16)?
+        .add_file("projects/crate/target/a.tmp", 1024)?
+        .add_file("projects/other/target/b.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_summary!(parser, "1.00KiB", num_dirs = 1, num_files = 0);
+    assert!(!mocks.test_dir().join("projects/crate/target").exists());
+    assert!(mocks.test_dir().join("projects/other/target").exists());
+
+    Ok(())
+}
+
+#[test]
+fn add_preset() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec![])?
+        .add_dir("projects/crate/target")?
+        .add_dir("projects/app/node_modules")?
+        .add_dir("projects/other/target")?
+        .add_file("projects/crate/Cargo.toml", 16)?
+        .add_file("projects/crate/target/a.tmp", 1024)?
+        .add_file("projects/app/package.json", 16)?
+        .add_file("projects/app/node_modules/b.tmp", 1024)?
+        .add_file("projects/other/target/c.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.current_dir(mocks.test_dir())
+        .arg("-c")
+        .arg(mocks.config_path())
+        .arg("preset")
+        .arg("add")
+        .arg("rust")
+        .arg("node")
+        .arg("--root")
+        .arg("projects");
+    cmd.assert().success();
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+
+    assert_pattern_entries!(
+        parser,
+        [
+            ("projects/**/target", "1.00KiB", num_dirs = 1, num_files = 0),
+            (
+                "projects/**/node_modules",
+                "1.00KiB",
+                num_dirs = 1,
+                num_files = 0
+            )
+        ],
+    );
+    assert_pattern_summary!(parser, "2.00KiB", num_dirs = 2, num_files = 0);
+
+    Ok(())
+}
+
+#[test]
+fn show_preset() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new().add_config(".clir", vec![])?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("preset")
+        .arg("show")
+        .arg("rust");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("<root>/**/target"))
+        .stdout(predicate::str::contains("Cargo.toml"));
+
+    Ok(())
+}
+
+#[test]
+fn list_patterns_json() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files", "file1"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 3 * 1024)?
+        .add_file("file1", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--format")
+        .arg("json");
+    let output = cmd.assert().success();
+    let report: serde_json::Value = serde_json::from_slice(&output.get_output().stdout)?;
+
+    let patterns = report["patterns"].as_array().unwrap();
+    assert_eq!(patterns.len(), 2);
+
+    let dir = patterns.iter().find(|p| p["rule"] == "test_files").unwrap();
+    let dir_path = mocks.test_dir().join("test_files");
+    assert_eq!(dir["pattern"], dir_path.to_str().unwrap());
+    assert_eq!(dir["paths"][0]["path"], dir_path.to_str().unwrap());
+    assert_eq!(dir["size"], 3 * 1024);
+    assert_eq!(dir["num_dirs"], 1);
+    assert_eq!(dir["num_files"], 0);
+    assert_eq!(dir["share"], 0.75);
+
+    assert_eq!(report["summary"]["size"], 4 * 1024);
+    assert_eq!(report["summary"]["num_dirs"], 1);
+    assert_eq!(report["summary"]["num_files"], 1);
+
+    // the prompt must not break the json on stdout
+    let mut cmd = assert_cmd::Command::from_std(mocks.command());
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--format")
+        .arg("json")
+        .arg("-r")
+        .write_stdin("n\n");
+    let output = cmd
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("Clean all selected paths?"))
+        .stderr(predicate::str::contains("Aborting..."));
+    let report: serde_json::Value = serde_json::from_slice(&output.get_output().stdout)?;
+    assert_eq!(report["summary"]["size"], 4 * 1024);
+
+    Ok(())
+}
+
+#[test]
+fn json_with_non_utf8_paths() -> anyhow::Result<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    // globs skip names which are not valid UTF-8, but followed symlinks do not
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config("[rules.links]\nglobs = [\"{dir}/links/*\"]\nsymlinks = \"follow\"\n")?
+        .add_dir("links")?;
+    let dir = mocks.test_dir().to_path_buf();
+    let target = dir.join(OsStr::from_bytes(b"data\xff"));
+    std::fs::create_dir(&target)?;
+    std::fs::write(target.join("a.tmp"), [0; 1024])?;
+    std::os::unix::fs::symlink(&target, dir.join("links/data"))?;
+    let lossy = target.to_string_lossy();
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--format")
+        .arg("json");
+    let output = cmd.assert().success();
+    let report: serde_json::Value = serde_json::from_slice(&output.get_output().stdout)?;
+    assert_eq!(report["patterns"][0]["paths"][0]["path"], *lossy);
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("report")
+        .arg("--format")
+        .arg("ndjson");
+    let output = cmd.assert().success();
+    let record: serde_json::Value = serde_json::from_slice(&output.get_output().stdout)?;
+    assert_eq!(record["path"], *lossy);
+
+    Ok(())
+}
+
+#[test]
+fn report_paths() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files/**/*.tmp", "test_files"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?
+        .add_file("test_files/b,c.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("report");
+    let output = cmd.assert().success();
+    let stdout = String::from_utf8(output.get_output().stdout.clone())?;
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+
+    let dir = mocks.test_dir().join("test_files");
+    let dir = dir.to_str().unwrap();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with(&format!("\"{dir}/b,c.tmp\",{dir}/**/*.tmp,1024,file,")));
+    assert!(lines[0].ends_with(",true,0,"));
+    assert!(lines[1].starts_with(&format!("{dir},{dir},2048,dir,")));
+    assert!(lines[1].ends_with(",false,0,"));
+    assert!(lines[2].starts_with(&format!("{dir}/a.tmp,")));
+    assert_eq!(
+        lines[3],
+        "path,pattern,size,type,mtime,shadowed,pinned_size,filtered"
+    );
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("report")
+        .arg("--format")
+        .arg("ndjson");
+    let output = cmd.assert().success();
+    let records = String::from_utf8(output.get_output().stdout.clone())?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+
+    assert_eq!(records.len(), 3);
+    let shadowed = records.iter().filter(|r| r["shadowed"] == true).count();
+    assert_eq!(shadowed, 2);
+    let record = records.iter().find(|r| r["path"] == dir).unwrap();
+    assert_eq!(record["type"], "dir");
+    assert_eq!(record["size"], 2048);
+    assert!(record["mtime"].is_string());
+    assert!(record["filtered"].is_null());
+
+    Ok(())
+}
+
+#[test]
+fn report_filtered_paths() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.tmp]
+globs = ["{dir}/test_files/*/*.tmp"]
+min_size = "1KiB"
+marker = "keep"
+"#,
+        )?
+        .add_dir("test_files/a")?
+        .add_dir("test_files/b")?
+        .add_file("test_files/a/keep", 0)?
+        .add_file("test_files/a/large.tmp", 2048)?
+        .add_file("test_files/a/small.tmp", 16)?
+        .add_file("test_files/b/large.tmp", 2048)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("report")
+        .arg("--format")
+        .arg("ndjson");
+    let output = cmd.assert().success();
+    let records = String::from_utf8(output.get_output().stdout.clone())?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+
+    let dir = mocks.test_dir().join("test_files");
+    let filtered = |path: &str| {
+        let path = dir.join(path);
+        let record = records.iter().find(|r| r["path"] == path.to_str().unwrap());
+        record.map(|r| r["filtered"].clone())
+    };
+    assert_eq!(records.len(), 3);
+    assert_eq!(filtered("a/large.tmp"), Some(serde_json::Value::Null));
+    assert_eq!(filtered("a/small.tmp"), Some("size".into()));
+    assert_eq!(filtered("b/large.tmp"), Some("marker".into()));
+
+    Ok(())
+}
+
+#[test]
+fn size_mode_disk() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files"])?
+        .add_dir("test_files")?;
+    File::create(mocks.test_dir().join("test_files/sparse.tmp"))?.set_len(1024 * 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+    assert_pattern_summary!(parser, "1.00MiB");
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--size-mode")
+        .arg("disk")
+        .arg("--format")
+        .arg("json");
+    let output = cmd.assert().success();
+    let report: serde_json::Value = serde_json::from_slice(&output.get_output().stdout)?;
+
+    // only the blocks of the directory itself are allocated
+    assert_eq!(report["summary"]["size_mode"], "disk");
+    assert!(report["summary"]["size"].as_u64().unwrap() < 1024 * 1024);
+
+    Ok(())
+}
+
+#[test]
+fn hard_links_counted_once() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["a", "b"])?
+        .add_dir("a")?
+        .add_dir("b")?
+        .add_dir("keep")?
+        .add_file("a/shared.tmp", 2048)?
+        .add_file("a/pinned.tmp", 1024)?;
+    let dir = mocks.test_dir();
+    std::fs::hard_link(dir.join("a/shared.tmp"), dir.join("b/shared.tmp"))?;
+    std::fs::hard_link(dir.join("a/pinned.tmp"), dir.join("keep/pinned.tmp"))?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--format")
+        .arg("json");
+    let output = cmd.assert().success();
+    let report: serde_json::Value = serde_json::from_slice(&output.get_output().stdout)?;
+
+    assert_eq!(report["summary"]["size"], 3 * 1024);
+    assert_eq!(report["summary"]["pinned_size"], 1024);
+    let pattern = report["patterns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|p| p["pinned_size"] == 1024)
+        .unwrap();
+    assert_eq!(pattern["paths"][0]["path"], dir.join("a").to_str().unwrap());
+    // the pattern sharing all its links is still listed for removal
+    assert_eq!(report["patterns"].as_array().unwrap().len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn protected_paths() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[options]
+protected = ["{dir}/projects/keep"]
+
+[rules.projects]
+globs = ["{dir}/projects/*"]
+"#,
+        )?
+        .add_dir("projects/keep")?
+        .add_file("projects/keep/a.tmp", 1024)?;
+
+    for pattern in ["/", "projects", "projects/keep/.."] {
+        let mut cmd = mocks.command();
+        cmd.current_dir(mocks.test_dir())
+            .arg("-c")
+            .arg(mocks.config_path())
+            .arg("add")
+            .arg(pattern);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("protected"));
+    }
+
+    let config = std::fs::read_to_string(mocks.config_path())?;
+    for root in ["/", "projects/keep"] {
+        let mut cmd = mocks.command();
+        cmd.current_dir(mocks.test_dir())
+            .arg("-c")
+            .arg(mocks.config_path())
+            .arg("preset")
+            .arg("add")
+            .arg("rust")
+            .arg("--root")
+            .arg(root);
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("protected"));
+    }
+    assert_eq!(std::fs::read_to_string(mocks.config_path())?, config);
+
+    // rules edited by hand are rejected before cleaning
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "refusing to clean rule `projects`",
+    ));
+    assert!(mocks.test_dir().join("projects/keep/a.tmp").exists());
+
+    Ok(())
+}
+
+#[test]
+fn symlink_policy() -> anyhow::Result<()> {
+    let config = |symlinks: &str| {
+        format!("[rules.links]\nglobs = [\"{{dir}}/links/*\"]\nsymlinks = \"{symlinks}\"\n")
+    };
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(&config("skip"))?
+        .add_dir("links")?
+        .add_dir("data")?
+        .add_file("data/a.tmp", 1024)?;
+    let dir = mocks.test_dir().to_path_buf();
+    std::os::unix::fs::symlink(dir.join("data"), dir.join("links/data"))?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+    assert!(dir.join("links/data").is_symlink());
+
+    let mocks = mocks.add_raw_config(&config("remove-link"))?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+    assert!(!dir.join("links/data").is_symlink());
+    assert!(dir.join("data/a.tmp").exists());
+
+    std::os::unix::fs::symlink(dir.join("data"), dir.join("links/data"))?;
+    let mocks = mocks.add_raw_config(&config("follow"))?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    let output = cmd.assert().success();
+    let parser = OutputParser::from_stdout(&output.get_output().stdout);
+    assert_pattern_summary!(parser, "1.00KiB", num_dirs = 1, num_files = 0);
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+    assert!(!dir.join("data").exists());
+
+    Ok(())
+}
+
+#[test]
+fn clean_failures_and_exit_codes() -> anyhow::Result<()> {
+    // the name of the trash info file exceeds the limit of the file system
+    let long_name = format!("test_files/{}.tmp", "a".repeat(248));
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files/*.tmp"])?
+        .add_dir("test_files")?
+        .add_file(&long_name, 1024)?
+        .add_file("test_files/b.tmp", 1024)?;
+
+    let mut cmd = assert_cmd::Command::from_std(mocks.command());
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .write_stdin("n\n");
+    cmd.assert().code(3);
+    assert!(mocks.test_dir().join("test_files/b.tmp").exists());
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .arg("-y")
+        .arg("--trash");
+    cmd.assert()
+        .code(2)
+        .stdout(predicates::str::contains("Freed 1.00KiB of 2.00KiB"))
+        .stderr(predicates::str::contains("Failed to clean 1 paths"))
+        .stderr(predicates::str::contains(&long_name));
+    assert!(!mocks.test_dir().join("test_files/b.tmp").exists());
+
+    Ok(())
+}
+
+#[test]
+fn select_patterns_and_paths() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files/*.tmp", "test_files/*.log"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?
+        .add_file("test_files/b.tmp", 1024)?
+        .add_file("test_files/c.log", 1024)?;
+
+    // patterns are listed by size, skip the logs and keep `a.tmp`
+    let mut cmd = assert_cmd::Command::from_std(mocks.command());
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .write_stdin("s\n1\np 2\n1\nb\nc\n");
+    cmd.assert().success();
+
+    assert!(mocks.test_dir().join("test_files/a.tmp").exists());
+    assert!(!mocks.test_dir().join("test_files/b.tmp").exists());
+    assert!(mocks.test_dir().join("test_files/c.log").exists());
+
+    let mut cmd = assert_cmd::Command::from_std(mocks.command());
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .write_stdin("s\nn\nc\n");
+    cmd.assert().code(3);
+    assert!(mocks.test_dir().join("test_files/c.log").exists());
+
+    Ok(())
+}
+
+#[test]
+fn size_cache() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files"])?
+        .add_dir("test_files/a")?
+        .add_file("test_files/a/b.tmp", 1024)?;
+    let cache_path = mocks.test_dir().join("clir/sizes");
+
+    let list = |args: &[&str]| {
+        let mut cmd = mocks.command();
+        cmd.arg("-c").arg(mocks.config_path()).args(args);
+        cmd.assert()
+    };
+
+    list(&["--no-cache"])
+        .success()
+        .stdout(predicate::str::contains("1.00KiB"));
+    assert!(!cache_path.exists());
+
+    list(&[])
+        .success()
+        .stdout(predicate::str::contains("1.00KiB"));
+    assert!(cache_path.is_file());
+
+    list(&["cache", "clear"])
+        .success()
+        .stdout(predicate::str::contains("Cleared the size cache"));
+    assert!(!cache_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn size_threshold_ignores_cache() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[rules.tmp]
+globs = ["{dir}/test_files"]
+min_size = "1KiB"
+max_size = "4KiB"
+"#,
+        )?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 2048)?;
+    // directories changed right before they are read are not cached
+    std::thread::sleep(Duration::from_millis(1100));
+
+    let list = || {
+        let mut cmd = mocks.command();
+        cmd.arg("-c").arg(mocks.config_path());
+        let output = cmd.assert().success();
+        OutputParser::from_stdout(&output.get_output().stdout)
+    };
+    let parser = list();
+    assert_pattern_summary!(parser, "2.00KiB", num_dirs = 1, num_files = 0);
+
+    // growing a file in place does not change its directory
+    std::fs::write(mocks.test_dir().join("test_files/a.tmp"), [0; 3072])?;
+    let parser = list();
+    assert_pattern_summary!(parser, "3.00KiB", num_dirs = 1, num_files = 0);
+
+    std::fs::write(mocks.test_dir().join("test_files/a.tmp"), [0; 5120])?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("-r").arg("-y");
+    cmd.assert().success();
+    assert!(mocks.test_dir().join("test_files/a.tmp").exists());
+
+    Ok(())
+}
+
+#[test]
+fn jobs_and_priority() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_raw_config(
+            r#"
+[options]
+jobs = 1
+low_priority = true
+
+[rules.test]
+globs = ["{dir}/test_files"]
+"#,
+        )?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?;
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("1.00KiB"));
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--jobs")
+        .arg("2")
+        .arg("--low-priority");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("1.00KiB"));
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--jobs")
+        .arg("0");
+    cmd.assert().failure();
+
+    let mocks = mocks.add_raw_config("[options]\njobs = 0\n")?;
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("expected a nonzero usize"));
+
+    Ok(())
+}
+
+#[test]
+fn concurrent_runs_are_locked() -> anyhow::Result<()> {
+    let mocks = mocks::MockFiles::new()
+        .add_config(".clir", vec!["test_files"])?
+        .add_dir("test_files")?
+        .add_file("test_files/a.tmp", 1024)?;
+    let lock_path = mocks.test_dir().join(".clir.lock");
+
+    // holds the lock while waiting for the confirmation
+    let mut first = mocks
+        .command()
+        .arg("-c")
+        .arg(mocks.config_path())
+        .arg("-r")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()?;
+    let pid = first.id().to_string();
+    let started = SystemTime::now();
+    while std::fs::read_to_string(&lock_path)
+        .unwrap_or_default()
+        .trim()
+        != pid
+    {
+        if let Some(status) = first.try_wait()? {
+            anyhow::bail!("first run exited before locking: {status}");
+        }
+        if started.elapsed()? > Duration::from_secs(10) {
+            first.kill()?;
+            anyhow::bail!("first run did not lock {lock_path:?}");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c").arg(mocks.config_path()).arg("--no-wait");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("clir process {pid}")));
+
+    let mut cmd = mocks.command();
+    cmd.arg("-c")
+        .arg(mocks.config_path())
+        .arg("--wait")
+        .arg("add")
+        .arg("other.tmp");
+    let mut second = cmd.spawn()?;
+
+    std::io::Write::write_all(&mut first.stdin.take().unwrap(), b"n\n")?;
+    assert_eq!(first.wait()?.code(), Some(3));
+    assert!(second.wait()?.success());
+    assert!(std::fs::read_to_string(mocks.config_path())?.contains("other.tmp"));
+
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use assert_cmd::prelude::*;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use std::path::Path;
 use std::str;
@@ -6,6 +7,7 @@ use std::{
     io::BufWriter,
     io::{self, Write},
     path::PathBuf,
+    process::{Command, Stdio},
 };
 
 #[derive(Default)]
@@ -38,12 +40,28 @@ impl MockFiles {
         &self.config_path
     }
 
+    /// Returns a clir command which keeps its trash, history and
+    /// size cache in the test directory instead of the real ones.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::cargo_bin("clir").unwrap();
+        cmd.env("XDG_DATA_HOME", &self.test_dir)
+            .env("XDG_CACHE_HOME", &self.test_dir);
+        cmd
+    }
+
     pub fn add_config(self, name: &str, patterns: Vec<&str>) -> io::Result<Self> {
         let path = self.test_dir.join(name);
         self.write_config_file(&path, patterns)?;
         Ok(self)
     }
 
+    /// Writes the config verbatim, `{dir}` is replaced with the test directory.
+    pub fn add_raw_config(self, content: &str) -> io::Result<Self> {
+        let content = content.replace("{dir}", &self.test_dir.to_string_lossy());
+        fs::write(&self.config_path, content)?;
+        Ok(self)
+    }
+
     pub fn add_dir(self, path: &str) -> io::Result<Self> {
         let path = self.test_dir.join(path);
         fs::create_dir_all(path)?;
@@ -58,7 +76,11 @@ impl MockFiles {
 
     pub fn write_config_file(&self, path: &Path, patterns: Vec<&str>) -> io::Result<()> {
         let _ = fs::remove_file(path);
-        let file = OpenOptions::new().write(true).create(true).open(path)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
 
         let mut file_buf = BufWriter::new(file);
         patterns
@@ -74,6 +96,7 @@ impl MockFiles {
         OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(path)
             .unwrap()
             .write(&[0].repeat(n))
@@ -86,6 +109,44 @@ impl Drop for MockFiles {
     }
 }
 
+/// A tmpfs mounted until it is dropped.
+pub struct Mount {
+    path: PathBuf,
+}
+
+impl Mount {
+    /// Mounts a tmpfs at the path, returns `None` if this is not
+    /// permitted, e.g. when the tests are not run as root.
+    pub fn tmpfs(path: &Path) -> Option<Self> {
+        fs::create_dir_all(path).ok()?;
+        let mounted = Command::new("mount")
+            .args(["-t", "tmpfs", "none"])
+            .arg(path)
+            .stderr(Stdio::null())
+            .status()
+            .ok()?
+            .success();
+        mounted.then(|| Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Remounts the file system read-only.
+    pub fn read_only(&self) -> bool {
+        Command::new("mount")
+            .args(["-o", "remount,ro"])
+            .arg(&self.path)
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.path).status();
+    }
+}
+
 #[derive(Debug)]
 pub enum Item {
     Break,
@@ -119,13 +180,13 @@ impl Item {
                             num_files: num_files.parse().unwrap(),
                             size_fmt: size_fmt.to_string(),
                         },
-                        [size_fmt, icon, count, pattern] if icon == "\u{f07b}" => Self::Entry {
+                        [size_fmt, "\u{f07b}", count, pattern] => Self::Entry {
                             pattern: pattern.to_string(),
                             num_dirs: count.parse().unwrap(),
                             num_files: 0,
                             size_fmt: size_fmt.to_string(),
                         },
-                        [size_fmt, icon, count, pattern] if icon == "\u{f0f6}" => Self::Entry {
+                        [size_fmt, "\u{f0f6}", count, pattern] => Self::Entry {
                             pattern: pattern.to_string(),
                             num_dirs: 0,
                             num_files: count.parse().unwrap(),